@@ -0,0 +1,82 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+/// A zstd-compressed tar archive that files are appended to before being deleted.
+///
+/// It is laid out as described on [`Disposal::Archive`](crate::Disposal::Archive).
+pub(crate) struct Archive {
+	path: PathBuf,
+	file: File,
+}
+
+impl Archive {
+	/// Open the archive at `path` for appending, creating it if needed.
+	///
+	/// The archive must not be within the directories being limited, which [`Disposal::check`](crate::Disposal::check) makes sure of.
+	pub fn open(path: &Path) -> Result<Self, Error> {
+		let file = OpenOptions::new()
+			.append(true)
+			.create(true)
+			.open(path)
+			.map_err(|error| Error::Archive(path.to_owned(), error))?;
+
+		Ok(Self {
+			path: path.to_owned(),
+			file,
+		})
+	}
+
+	/// Append the file at `path` to the archive as `name`, with its metadata.
+	///
+	/// The archive is synced before returning, so that the file can be deleted safely.
+	pub fn append(&mut self, path: &Path, name: &Path) -> Result<(), Error> {
+		let length = self
+			.file
+			.metadata()
+			.map_err(|error| Error::Archive(self.path.clone(), error))?
+			.len();
+		let appended = self.append_frame(path, name);
+		if appended.is_err() {
+			// Don't leave a partial frame behind, which would make the rest of the archive unreadable.
+			let _ = self.file.set_len(length);
+		}
+		appended.map_err(|error| Error::Archive(path.to_owned(), error))
+	}
+
+	fn append_frame(&mut self, path: &Path, name: &Path) -> io::Result<()> {
+		let encoder = zstd::Encoder::new(&self.file, 0)?;
+		let mut builder = tar::Builder::new(Untrailed {
+			inner: encoder,
+			done: false,
+		});
+		// Symbolic links are archived as links, like they are deleted.
+		builder.follow_symlinks(false);
+		builder.append_path_with_name(path, name)?;
+		builder.get_mut().done = true;
+		builder.into_inner()?.inner.finish()?;
+		self.file.sync_data()
+	}
+}
+
+/// Drops the end-of-archive marker that [`tar::Builder`] writes once `done` is set,
+/// so that the frames of the archive form a single tar stream once decompressed.
+struct Untrailed<W> {
+	inner: W,
+	done: bool,
+}
+
+impl<W: Write> Write for Untrailed<W> {
+	fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+		if self.done {
+			return Ok(buffer.len());
+		}
+		self.inner.write(buffer)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::plan::{root, Candidate, Links};
+use crate::{Goal, Usage};
+
+/// The sizes of the child directories limited by [`Goal::per_child`].
+pub(crate) struct Children<'a> {
+	directories: &'a [PathBuf],
+	limit: u64,
+	depth: usize,
+	sizes: HashMap<PathBuf, Child>,
+	/// The number of children over the limit.
+	pub over: u64,
+}
+
+#[derive(Default)]
+struct Child {
+	size: u64,
+	/// The links to each inode within the child, which is counted once like in the whole directory.
+	links: HashMap<(u64, u64), Links>,
+}
+
+impl<'a> Children<'a> {
+	pub fn new(directories: &'a [PathBuf], goal: Goal, files: &[Candidate], usage: Usage) -> Self {
+		let mut this = Self {
+			directories,
+			limit: goal.per_child,
+			depth: goal.child_depth,
+			sizes: HashMap::new(),
+			over: 0,
+		};
+		if this.limit == u64::MAX {
+			return this;
+		}
+
+		for file in files {
+			let child = this.sizes.entry(this.key(&file.path)).or_default();
+			let links = child.links.entry(file.inode()).or_default();
+			if links.count == 0 {
+				links.usage = usage.get(&file.metadata);
+				child.size += links.usage;
+			}
+			links.count += 1;
+		}
+		this.over = this
+			.sizes
+			.values()
+			.filter(|child| child.size > this.limit)
+			.count() as u64;
+		this
+	}
+
+	/// Whether the child containing `file` is over the limit.
+	pub fn is_over(&self, file: &Candidate) -> bool {
+		self.over > 0
+			&& self
+				.sizes
+				.get(&self.key(&file.path))
+				.is_some_and(|child| child.size > self.limit)
+	}
+
+	/// Account for `file` being deleted.
+	///
+	/// The child gets smaller once its last link to the inode is gone, even if links in other children remain.
+	pub fn remove(&mut self, file: &Candidate) {
+		if self.limit == u64::MAX {
+			return;
+		}
+		let key = self.key(&file.path);
+		let Some(child) = self.sizes.get_mut(&key) else {
+			return;
+		};
+		let Some(links) = child.links.get_mut(&file.inode()) else {
+			return;
+		};
+		links.count -= 1;
+		if links.count > 0 {
+			return;
+		}
+		let was_over = child.size > self.limit;
+		child.size = child.size.saturating_sub(links.usage);
+		if was_over && child.size <= self.limit {
+			self.over -= 1;
+		}
+	}
+
+	/// The child containing `path`, which is its directory `depth` levels down,
+	/// or the closest one to it for files that are not that deep.
+	fn key(&self, path: &Path) -> PathBuf {
+		let root = root(self.directories, path);
+		let parent = path.parent().unwrap_or(root);
+		let relative = parent.strip_prefix(root).unwrap_or(parent);
+		root.join(relative.components().take(self.depth).collect::<PathBuf>())
+	}
+}
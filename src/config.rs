@@ -0,0 +1,151 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use sau::{Amount, OnError, Options, TimeKey, Usage};
+use serde::{Deserialize, Deserializer};
+
+/// A configuration file describing several directories to limit.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+	directory: Vec<Job>,
+}
+
+/// A directory to limit, or a pool of directories that share a limit, with their own settings.
+///
+/// These mirror the command-line options, which describe a single job.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Job {
+	/// Either a single path or an array of paths in the file.
+	#[serde(rename = "path", deserialize_with = "one_or_many")]
+	pub paths: Vec<PathBuf>,
+	#[serde(deserialize_with = "parse_option")]
+	pub size: Option<Amount>,
+	#[serde(deserialize_with = "parse_option")]
+	pub high: Option<Amount>,
+	#[serde(deserialize_with = "parse_option")]
+	pub low: Option<Amount>,
+	#[serde(deserialize_with = "parse_option")]
+	pub min_free: Option<Amount>,
+	pub max_files: Option<u64>,
+	#[serde(deserialize_with = "parse_option")]
+	pub per_child: Option<Amount>,
+	pub child_depth: usize,
+	#[serde(deserialize_with = "parse")]
+	pub time: TimeKey,
+	#[serde(deserialize_with = "parse_option")]
+	pub usage: Option<Usage>,
+	#[serde(deserialize_with = "parse")]
+	pub on_error: OnError,
+	pub include: Vec<String>,
+	pub exclude: Vec<String>,
+	pub count_deletable_only: bool,
+	pub keep_parents: bool,
+	#[serde(deserialize_with = "parse_duration")]
+	pub min_age: Duration,
+	pub skip_open: bool,
+	pub respect_locks: bool,
+	pub trash: bool,
+	pub quarantine: Option<PathBuf>,
+	#[serde(deserialize_with = "parse_duration")]
+	pub grace: Duration,
+	pub archive: Option<PathBuf>,
+}
+
+impl Default for Job {
+	fn default() -> Self {
+		let options = Options::default();
+		Self {
+			paths: Vec::new(),
+			size: None,
+			high: None,
+			low: None,
+			min_free: None,
+			max_files: None,
+			per_child: None,
+			child_depth: 1,
+			time: options.time,
+			usage: None,
+			on_error: options.on_error,
+			include: Vec::new(),
+			exclude: Vec::new(),
+			count_deletable_only: false,
+			keep_parents: options.keep_parents,
+			min_age: options.min_age,
+			skip_open: options.skip_open,
+			respect_locks: options.respect_locks,
+			trash: false,
+			quarantine: None,
+			grace: DEFAULT_GRACE,
+			archive: None,
+		}
+	}
+}
+
+/// How long files stay in the quarantine by default.
+const DEFAULT_GRACE: Duration = Duration::from_hours(24);
+
+/// Read the jobs from the configuration file at `path`.
+///
+/// Relative paths in the file are relative to the directory containing it.
+pub fn load(path: &Path) -> Result<Vec<Job>, String> {
+	let raw = std::fs::read_to_string(path)
+		.map_err(|error| format!("error reading configuration from {path:?}: {error}"))?;
+	let config: Config =
+		toml::from_str(&raw).map_err(|error| format!("invalid configuration in {path:?}: {error}"))?;
+
+	let base = path.parent().unwrap_or(Path::new(""));
+	let mut jobs = config.directory;
+	for job in &mut jobs {
+		if job.paths.is_empty() {
+			return Err(format!(
+				"invalid configuration in {path:?}: every directory needs a `path`"
+			));
+		}
+		for path in job
+			.paths
+			.iter_mut()
+			.chain(job.quarantine.as_mut())
+			.chain(job.archive.as_mut())
+		{
+			*path = base.join(&*path);
+		}
+	}
+	Ok(jobs)
+}
+
+fn parse<'de, D: Deserializer<'de>, T: FromStr<Err: Display>>(
+	deserializer: D,
+) -> Result<T, D::Error> {
+	String::deserialize(deserializer)?
+		.parse()
+		.map_err(serde::de::Error::custom)
+}
+
+fn parse_option<'de, D: Deserializer<'de>, T: FromStr<Err: Display>>(
+	deserializer: D,
+) -> Result<Option<T>, D::Error> {
+	parse(deserializer).map(Some)
+}
+
+fn parse_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+	parse::<D, humantime::Duration>(deserializer).map(Into::into)
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum OneOrMany {
+		One(PathBuf),
+		Many(Vec<PathBuf>),
+	}
+
+	Ok(match OneOrMany::deserialize(deserializer)? {
+		OneOrMany::One(path) => vec![path],
+		OneOrMany::Many(paths) => paths,
+	})
+}
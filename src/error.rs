@@ -0,0 +1,206 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// An I/O error that affected a single file or directory.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+	/// Reading a directory failed.
+	Walk(PathBuf, io::Error),
+	/// Getting the metadata of a file failed.
+	Metadata(PathBuf, io::Error),
+	/// Deleting a file failed.
+	Delete(PathBuf, io::Error),
+	/// Watching a directory for changes failed.
+	Watch(PathBuf, io::Error),
+	/// Getting the space on the filesystem containing a directory failed.
+	Statvfs(PathBuf, io::Error),
+	/// Reading a `.sauignore` or `.saukeep` file failed.
+	///
+	/// Everything in the directory containing it is kept.
+	Rules(PathBuf, io::Error),
+	/// Listing the files that processes have open failed.
+	OpenFiles(PathBuf, io::Error),
+	/// Opening or locking a file before deleting it failed.
+	Lock(PathBuf, io::Error),
+	/// Moving a file to the trash failed.
+	Trash(PathBuf, io::Error),
+	/// Moving a file into the quarantine failed.
+	Quarantine(PathBuf, io::Error),
+	/// Moving a file out of the quarantine failed.
+	Restore(PathBuf, io::Error),
+	/// Opening the archive or appending a file to it failed.
+	Archive(PathBuf, io::Error),
+}
+
+impl Error {
+	/// The path of the file or directory that the error affected.
+	#[must_use]
+	pub fn path(&self) -> &Path {
+		match self {
+			Self::Walk(path, _)
+			| Self::Metadata(path, _)
+			| Self::Delete(path, _)
+			| Self::Watch(path, _)
+			| Self::Statvfs(path, _)
+			| Self::Rules(path, _)
+			| Self::OpenFiles(path, _)
+			| Self::Lock(path, _)
+			| Self::Trash(path, _)
+			| Self::Quarantine(path, _)
+			| Self::Restore(path, _)
+			| Self::Archive(path, _) => path,
+		}
+	}
+
+	/// The underlying I/O error.
+	#[must_use]
+	pub fn io_error(&self) -> &io::Error {
+		match self {
+			Self::Walk(_, error)
+			| Self::Metadata(_, error)
+			| Self::Delete(_, error)
+			| Self::Watch(_, error)
+			| Self::Statvfs(_, error)
+			| Self::Rules(_, error)
+			| Self::OpenFiles(_, error)
+			| Self::Lock(_, error)
+			| Self::Trash(_, error)
+			| Self::Quarantine(_, error)
+			| Self::Restore(_, error)
+			| Self::Archive(_, error) => error,
+		}
+	}
+
+	fn operation(&self) -> &'static str {
+		match self {
+			Self::Walk(..) => "walking",
+			Self::Metadata(..) => "getting metadata of",
+			Self::Delete(..) => "deleting",
+			Self::Watch(..) => "watching",
+			Self::Statvfs(..) => "getting filesystem usage of",
+			Self::Rules(..) => "reading protection rules from",
+			Self::OpenFiles(..) => "listing open files in",
+			Self::Lock(..) => "locking",
+			Self::Trash(..) => "moving to the trash",
+			Self::Quarantine(..) => "quarantining",
+			Self::Restore(..) => "restoring",
+			Self::Archive(..) => "archiving",
+		}
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			formatter,
+			"error {} {:?}: {}",
+			self.operation(),
+			self.path(),
+			self.io_error()
+		)
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		Some(self.io_error())
+	}
+}
+
+/// What to do when an I/O error affects a single file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+	/// Record the error, leave the file alone, and carry on with the rest.
+	Skip,
+	/// Stop immediately and return the error.
+	Abort,
+}
+
+impl FromStr for OnError {
+	type Err = String;
+
+	fn from_str(raw: &str) -> Result<Self, Self::Err> {
+		Ok(match raw {
+			"skip" => Self::Skip,
+			"abort" => Self::Abort,
+			_ => {
+				return Err(format!(
+					"unknown error policy {raw:?}, expected one of skip, abort"
+				))
+			}
+		})
+	}
+}
+
+/// Applies an [`OnError`] policy and collects the errors that were skipped.
+#[derive(Debug)]
+pub(crate) struct Errors {
+	policy: OnError,
+	pub skipped: Vec<Error>,
+}
+
+impl Errors {
+	pub fn new(policy: OnError) -> Self {
+		Self {
+			policy,
+			skipped: Vec::new(),
+		}
+	}
+
+	/// Returns `Ok(None)` if the error was skipped, in which case the caller should leave the affected file alone.
+	pub fn check<T>(&mut self, result: Result<T, Error>) -> Result<Option<T>, Error> {
+		match result {
+			Ok(inner) => Ok(Some(inner)),
+			Err(error) => self.skip(error).map(|_| None),
+		}
+	}
+
+	/// Returns the recorded error if it was skipped.
+	pub fn skip(&mut self, error: Error) -> Result<&Error, Error> {
+		match self.policy {
+			OnError::Skip => {
+				self.skipped.push(error);
+				Ok(&self.skipped[self.skipped.len() - 1])
+			}
+			OnError::Abort => Err(error),
+		}
+	}
+}
+
+pub(crate) trait IoResultExt {
+	type Ok;
+
+	fn or_error(
+		self,
+		variant: fn(PathBuf, io::Error) -> Error,
+		path: &Path,
+	) -> Result<Self::Ok, Error>;
+}
+
+impl<T> IoResultExt for io::Result<T> {
+	type Ok = T;
+
+	fn or_error(self, variant: fn(PathBuf, io::Error) -> Error, path: &Path) -> Result<T, Error> {
+		self.map_err(|error| variant(path.to_owned(), error))
+	}
+}
+
+impl<T> IoResultExt for walkdir::Result<T> {
+	type Ok = T;
+
+	fn or_error(self, variant: fn(PathBuf, io::Error) -> Error, path: &Path) -> Result<T, Error> {
+		// Prefer the path that actually failed over the root of the walk.
+		self.map_err(|error| {
+			let path = error.path().unwrap_or(path).to_owned();
+			// Unwrap plain I/O errors so the path isn't repeated in the message.
+			let error = if error.io_error().is_some() {
+				error.into_io_error().unwrap()
+			} else {
+				error.into()
+			};
+			variant(path, error)
+		})
+	}
+}
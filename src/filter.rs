@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Which files may be deleted, using gitignore-style patterns.
+///
+/// Patterns are matched against paths relative to the directory being limited,
+/// so `*.lock` matches at any depth while `/cache/*.lock` only matches at the top.
+#[derive(Debug, Clone)]
+pub struct Filter {
+	/// If present, only files matching these patterns may be deleted.
+	include: Option<Gitignore>,
+	/// Files matching these patterns are never deleted.
+	exclude: Option<Gitignore>,
+	/// Whether files that the patterns don't allow to be deleted still count towards the size and number of files.
+	///
+	/// Files protected by `.sauignore` and `.saukeep` always count.
+	pub count_excluded: bool,
+}
+
+impl Filter {
+	/// Only allow deleting files that match one of `include`, if it is not empty, and none of `exclude`.
+	///
+	/// Files that may not be deleted still count towards the size and number of files.
+	///
+	/// # Errors
+	///
+	/// If a pattern is invalid.
+	pub fn new(
+		include: &[impl AsRef<str>],
+		exclude: &[impl AsRef<str>],
+	) -> Result<Self, ignore::Error> {
+		Ok(Self {
+			include: matcher(include)?,
+			exclude: matcher(exclude)?,
+			count_excluded: true,
+		})
+	}
+
+	/// Whether the file at `relative`, which is relative to the directory being limited, may be deleted.
+	#[must_use]
+	pub fn allows(&self, relative: &Path) -> bool {
+		let matches = |patterns: &Gitignore| {
+			patterns
+				.matched_path_or_any_parents(relative, false)
+				.is_ignore()
+		};
+		self.include.as_ref().is_none_or(matches) && !self.exclude.as_ref().is_some_and(matches)
+	}
+}
+
+impl Default for Filter {
+	/// Allow deleting any file.
+	fn default() -> Self {
+		Self {
+			include: None,
+			exclude: None,
+			count_excluded: true,
+		}
+	}
+}
+
+fn matcher(patterns: &[impl AsRef<str>]) -> Result<Option<Gitignore>, ignore::Error> {
+	if patterns.is_empty() {
+		return Ok(None);
+	}
+
+	let mut builder = GitignoreBuilder::new(".");
+	for pattern in patterns {
+		builder.add_line(None, pattern.as_ref())?;
+	}
+	builder.build().map(Some)
+}
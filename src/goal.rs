@@ -0,0 +1,204 @@
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+use bytesize::ByteSize;
+
+use crate::Error;
+
+/// How large a directory may get, and how far to shrink it once it gets too large.
+///
+/// All of the constraints are satisfied together, so files are deleted until the most demanding one is met.
+///
+/// Deleting files only until the size is back under the limit means that the next write exceeds it again.
+/// Separate high and low watermarks avoid that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Goal {
+	/// Nothing is deleted unless the size exceeds this many bytes.
+	pub high: u64,
+	/// Once something needs to be deleted, files are deleted until the size is at most this many bytes.
+	pub low: u64,
+	/// At least this many bytes are freed, regardless of the size.
+	pub reclaim: u64,
+	/// Files are deleted until there are at most this many.
+	///
+	/// Hard links to the same file are counted once, since deleting one of them doesn't free an inode.
+	pub max_files: u64,
+	/// Files are deleted from each child directory until it is at most this many bytes, see [`Goal::and_per_child`].
+	pub per_child: u64,
+	/// How many levels down the child directories limited by `per_child` are.
+	pub child_depth: usize,
+}
+
+impl Goal {
+	/// Limit the size to `size` bytes, without hysteresis.
+	#[must_use]
+	pub fn new(size: u64) -> Self {
+		Self {
+			high: size,
+			low: size,
+			reclaim: 0,
+			max_files: u64::MAX,
+			per_child: u64::MAX,
+			child_depth: 1,
+		}
+	}
+
+	/// Free at least `bytes` bytes, regardless of the size.
+	#[must_use]
+	pub fn reclaim(bytes: u64) -> Self {
+		Self {
+			high: u64::MAX,
+			low: u64::MAX,
+			reclaim: bytes,
+			max_files: u64::MAX,
+			per_child: u64::MAX,
+			child_depth: 1,
+		}
+	}
+
+	/// Do nothing until the size exceeds `high` bytes, then delete files until it is at most `low` bytes.
+	///
+	/// # Panics
+	///
+	/// If `low` is greater than `high`.
+	#[must_use]
+	pub fn watermarks(high: u64, low: u64) -> Self {
+		assert!(
+			low <= high,
+			"the low watermark must not be greater than the high watermark"
+		);
+		Self {
+			high,
+			low,
+			reclaim: 0,
+			max_files: u64::MAX,
+			per_child: u64::MAX,
+			child_depth: 1,
+		}
+	}
+
+	/// Also free at least `bytes` bytes, regardless of the size.
+	#[must_use]
+	pub fn and_reclaim(self, bytes: u64) -> Self {
+		Self {
+			reclaim: self.reclaim.max(bytes),
+			..self
+		}
+	}
+
+	/// Also limit the number of files to `files`.
+	#[must_use]
+	pub fn and_max_files(self, files: u64) -> Self {
+		Self {
+			max_files: self.max_files.min(files),
+			..self
+		}
+	}
+
+	/// Also limit each directory `depth` levels down to `bytes` bytes on its own,
+	/// so that one of them can't grow at the expense of the others.
+	///
+	/// Files that are not that deep are limited together with the directory containing them.
+	/// Within each child, files are still deleted least-recently used first.
+	#[must_use]
+	pub fn and_per_child(self, bytes: u64, depth: usize) -> Self {
+		Self {
+			per_child: self.per_child.min(bytes),
+			child_depth: depth,
+			..self
+		}
+	}
+
+	/// The size to delete files down to, given the current size.
+	#[must_use]
+	pub fn target(self, size: u64) -> u64 {
+		let target = if size > self.high { self.low } else { size };
+		target.min(size.saturating_sub(self.reclaim))
+	}
+}
+
+impl From<u64> for Goal {
+	fn from(size: u64) -> Self {
+		Self::new(size)
+	}
+}
+
+/// An amount of space, either absolute or relative to the capacity of a filesystem.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Amount {
+	/// A number of bytes.
+	Bytes(u64),
+	/// A percentage of the capacity of the filesystem.
+	Percent(f64),
+}
+
+impl Amount {
+	/// Get the number of bytes, given the capacity of the filesystem.
+	#[must_use]
+	#[allow(
+		clippy::cast_possible_truncation,
+		clippy::cast_precision_loss,
+		clippy::cast_sign_loss
+	)]
+	pub fn resolve(self, capacity: u64) -> u64 {
+		match self {
+			Self::Bytes(bytes) => bytes,
+			// Precision loss is fine, this is a percentage.
+			Self::Percent(percent) => (capacity as f64 * percent / 100.0) as u64,
+		}
+	}
+}
+
+impl FromStr for Amount {
+	type Err = String;
+
+	fn from_str(raw: &str) -> Result<Self, Self::Err> {
+		if let Some(percent) = raw.strip_suffix('%') {
+			let percent: f64 = percent
+				.trim()
+				.parse()
+				.map_err(|error| format!("invalid percentage {raw:?}: {error}"))?;
+			if !(0.0..=100.0).contains(&percent) {
+				return Err(format!("percentage {raw:?} is not between 0% and 100%"));
+			}
+			Ok(Self::Percent(percent))
+		} else {
+			raw.parse().map(|ByteSize(bytes)| Self::Bytes(bytes))
+		}
+	}
+}
+
+impl Display for Amount {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Bytes(bytes) => write!(formatter, "{}", ByteSize(*bytes)),
+			Self::Percent(percent) => write!(formatter, "{percent}%"),
+		}
+	}
+}
+
+/// The space on the filesystem containing a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filesystem {
+	/// The total size of the filesystem, in bytes.
+	pub capacity: u64,
+	/// The space available to unprivileged users, in bytes.
+	pub available: u64,
+}
+
+impl Filesystem {
+	/// Get the space on the filesystem containing `path`.
+	///
+	/// # Errors
+	///
+	/// If `statvfs` fails.
+	pub fn containing(path: &Path) -> Result<Self, Error> {
+		let stat =
+			rustix::fs::statvfs(path).map_err(|error| Error::Statvfs(path.to_owned(), error.into()))?;
+		Ok(Self {
+			capacity: stat.f_blocks * stat.f_frsize,
+			available: stat.f_bavail * stat.f_frsize,
+		})
+	}
+}
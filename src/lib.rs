@@ -0,0 +1,53 @@
+//! Delete least-recently used files to limit a directory to a specified size.
+//!
+//! Eviction is split into two steps so that callers can inspect what would happen before anything is deleted:
+//! [`Plan::compute`] walks the directory and chooses victims, and [`Plan::execute`] deletes them.
+//!
+//! ```no_run
+//! use sau::{Options, Plan};
+//!
+//! let plan = Plan::compute("/var/cache/things", 1 << 30, Options::default())?;
+//! println!("would delete {} files", plan.victims.len());
+//! plan.execute(|_event| {})?;
+//! # Ok::<(), sau::Error>(())
+//! ```
+
+#![deny(
+	absolute_paths_not_starting_with_crate,
+	keyword_idents,
+	macro_use_extern_crate,
+	meta_variable_misuse,
+	missing_abi,
+	missing_copy_implementations,
+	missing_docs,
+	non_ascii_idents,
+	nonstandard_style,
+	noop_method_call,
+	rust_2018_idioms,
+	unused_qualifications
+)]
+#![warn(clippy::pedantic)]
+// Paths are printed with `Debug` on purpose so that unusual file names are quoted and escaped.
+#![allow(clippy::unnecessary_debug_formatting)]
+#![forbid(unsafe_code)]
+
+mod archive;
+mod children;
+mod error;
+mod filter;
+mod goal;
+mod open;
+mod options;
+mod plan;
+mod protect;
+mod quarantine;
+mod trash;
+mod watch;
+
+pub use crate::error::{Error, OnError};
+pub use crate::filter::Filter;
+pub use crate::goal::{Amount, Filesystem, Goal};
+pub use crate::options::{Disposal, Options, TimeKey, Usage};
+pub use crate::plan::{Event, Outcome, Plan, Reason, Victim};
+pub use crate::quarantine::Quarantine;
+pub use crate::watch::Watcher;
@@ -8,59 +8,35 @@
 	non_ascii_idents,
 	nonstandard_style,
 	noop_method_call,
-	pointer_structural_match,
-	private_in_public,
 	rust_2018_idioms,
 	unused_qualifications
 )]
 #![warn(clippy::pedantic)]
+// Paths are printed with `Debug` on purpose so that unusual file names are quoted and escaped.
+#![allow(clippy::unnecessary_debug_formatting)]
 #![forbid(unsafe_code)]
 
-use std::fs::FileType;
-use std::os::unix::fs::MetadataExt as _;
+mod config;
+mod metrics;
+mod report;
+
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
 use bytesize::ByteSize;
-use walkdir::WalkDir;
-
-trait IoResultExt {
-	type Ok;
-
-	fn unwrap_io(self, operation: &str, path: &Path) -> Self::Ok;
-	fn unwrap_io_lazy<P: AsRef<Path>>(self, operation: &str, path: impl FnOnce() -> P) -> Self::Ok;
-}
-
-impl<T, E> IoResultExt for Result<T, E>
-where
-	E: std::fmt::Display,
-{
-	type Ok = T;
-
-	#[track_caller]
-	fn unwrap_io(self, operation: &str, path: &Path) -> T {
-		// Not using `unwrap_or_else` to preserve caller location.
-		match self {
-			Ok(inner) => inner,
-			Err(error) => {
-				panic!("error {operation} {path:?}: {error}");
-			}
-		}
-	}
-
-	#[track_caller]
-	fn unwrap_io_lazy<P: AsRef<Path>>(self, operation: &str, path: impl FnOnce() -> P) -> T {
-		// Ditto.
-		match self {
-			Ok(inner) => inner,
-			Err(error) => {
-				panic!("error {operation} {:?}: {error}", path().as_ref());
-			}
-		}
-	}
-}
+use config::Job;
+use metrics::{Metrics, Run};
+use report::{Format, Record, Reporter};
+use sau::{
+	Amount, Disposal, Error, Event, Filesystem, Filter, Goal, OnError, Options, Outcome, Plan,
+	Quarantine, TimeKey, Usage, Victim, Watcher,
+};
 
 /// Delete least-recently used files to limit a directory to a specified size.
 #[derive(argh::FromArgs)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
 	/// don't actually delete anything
 	#[argh(switch, short = 'd')]
@@ -71,97 +47,921 @@ struct Args {
 	/// hence the default behavior of deleting them for cleanliness.
 	#[argh(switch, short = 'k')]
 	keep_parents: bool,
-	/// the size to limit the directory to
+	/// the size to limit the directory to,
+	/// either absolute (1GB) or a percentage of the capacity of the filesystem containing it (30%)
 	///
 	/// Files will be deleted until this size is reached.
+	/// Percentages are resolved every time the size is checked.
 	#[argh(option, short = 's')]
-	size: ByteSize,
-	/// the directory to process
+	size: Option<Amount>,
+	/// the size above which files start being deleted, used with `--low`
+	///
+	/// This is an alternative to `--size` that avoids deleting files every time something is written
+	/// once the directory is near its limit.
+	#[argh(option)]
+	high: Option<Amount>,
+	/// the size to delete files down to once `--high` is exceeded
+	#[argh(option)]
+	low: Option<Amount>,
+	/// the space to keep free on the filesystem containing the directory,
+	/// either absolute (50GB) or a percentage of its capacity (20%)
+	///
+	/// Files are deleted from the directory until this much space is available.
+	/// This can be used on its own or together with a size limit.
+	#[argh(option)]
+	min_free: Option<Amount>,
+	/// the number of files to limit the directory to
+	///
+	/// This can be used on its own or together with the other limits,
+	/// in which case files are deleted until all of them are satisfied.
+	/// Hard links to the same file are counted once.
+	#[argh(option)]
+	max_files: Option<u64>,
+	/// the size to limit each child directory to on its own,
+	/// either absolute (5GB) or a percentage of the capacity of the filesystem (10%)
+	///
+	/// This keeps one child, like a tenant of a shared cache, from growing at the expense of the others.
+	/// It can be used on its own or together with the other limits.
+	#[argh(option)]
+	per_child: Option<Amount>,
+	/// how many levels down the directories limited by `--per-child` are (default 1)
+	///
+	/// With 2, each `tenant/project` directory is limited on its own.
+	/// Files that are not that deep are limited together with the directory containing them.
+	#[argh(option)]
+	child_depth: Option<usize>,
+	/// the timestamp that determines which files are least-recently used:
+	/// atime, mtime, ctime, btime, or max (default mtime)
+	///
+	/// On filesystems mounted with `noatime`, access times are never updated, so `atime` uses the later of the access and modification times.
+	/// If birth times are not supported, `btime` falls back to the modification time with a warning.
+	/// `max` uses the latest of all available timestamps.
+	#[argh(option, short = 't')]
+	time: Option<TimeKey>,
+	/// how to measure file sizes: apparent or blocks
+	/// (default apparent, or blocks with `--min-free`)
+	///
+	/// `apparent` uses the length of each file, like `du --apparent-size`.
+	/// `blocks` uses the space actually allocated on disk, like `du`,
+	/// which accounts for sparse files and block rounding.
+	#[argh(option, short = 'u')]
+	usage: Option<Usage>,
+	/// what to do when a file or directory can't be read or deleted:
+	/// skip or abort (default skip)
+	///
+	/// With `skip`, the affected file is left alone, the run carries on,
+	/// and the errors are summarized at the end with an exit code of 2.
+	#[argh(option)]
+	on_error: Option<OnError>,
+	/// only delete files matching this gitignore-style pattern,
+	/// can be given multiple times
+	#[argh(option)]
+	include: Vec<String>,
+	/// never delete files matching this gitignore-style pattern,
+	/// can be given multiple times
+	///
+	/// Patterns are relative to the directory, so `*.lock` matches at any depth,
+	/// while `/top/*.lock` only matches within the `top` directory.
+	#[argh(option)]
+	exclude: Vec<String>,
+	/// don't count files that may not be deleted because of `--include` or `--exclude`
+	/// towards the size or the number of files
+	#[argh(switch)]
+	count_deletable_only: bool,
+	/// keep running and delete files as soon as the limit is exceeded
+	///
+	/// Changes to the directory are tracked with inotify,
+	/// so the directory is only walked once at startup.
+	#[argh(switch, short = 'w')]
+	watch: bool,
+	/// never delete files more recent than this by the selected timestamp,
+	/// such as 10m (default 0s)
+	///
+	/// A file that is still being written can have an old timestamp if it was preallocated
+	/// or copied with its timestamps preserved.
+	#[argh(option)]
+	min_age: Option<humantime::Duration>,
+	/// never delete files that a process has open, which frees no space until they are closed
+	///
+	/// Open files are found by looking through `/proc/*/fd`, which only works on Linux
+	/// and only sees the processes of other users when running as root.
+	#[argh(switch)]
+	skip_open: bool,
+	/// try to take an exclusive `flock` on each file before deleting it,
+	/// and leave it alone if another process holds a lock on it
+	#[argh(switch)]
+	respect_locks: bool,
+	/// move files to the trash instead of deleting them, so that they can be restored
+	///
+	/// The trash is the one in `$XDG_DATA_HOME` if it is on the same filesystem,
+	/// otherwise the `.Trash-$UID` directory at the top of the filesystem.
+	/// This only frees space in the directory, not on the filesystem.
+	#[argh(switch)]
+	trash: bool,
+	/// move files into this directory instead of deleting them,
+	/// and only delete them once `--grace` has elapsed
+	///
+	/// The quarantine must be on the same filesystem as the directory but not within it.
+	/// Quarantined files are deleted for good by later runs with the same `--quarantine`,
+	/// and can be moved back with the `restore` subcommand until then.
+	#[argh(option)]
+	quarantine: Option<PathBuf>,
+	/// append files to this zstd-compressed tar archive before deleting them
+	#[argh(option)]
+	archive: Option<PathBuf>,
+	/// how long files stay in the quarantine before being deleted for good (default 24h)
+	#[argh(option)]
+	grace: Option<humantime::Duration>,
+	/// print records of what happens to stdout: text, json, or ndjson (default text)
+	///
+	/// `text` only prints the messages on stderr.
+	/// `json` prints an array of all records once done, and `ndjson` prints each record on its own line as it happens.
+	/// The messages on stderr are printed regardless.
+	#[argh(option, default = "Format::Text")]
+	format: Format,
+	/// write metrics in the Prometheus text format to this file after every run,
+	/// for the textfile collector of `node_exporter`
+	///
+	/// The file is replaced atomically, and its name should end in `.prom`.
+	#[argh(option)]
+	metrics_file: Option<PathBuf>,
+	/// in watch mode, serve the same metrics over HTTP on this address, such as 127.0.0.1:9833
+	#[argh(option)]
+	metrics_listen: Option<SocketAddr>,
+	/// in watch mode, how long to wait after a change for further changes
+	/// before checking the size (default 1s)
+	#[argh(option)]
+	debounce: Option<humantime::Duration>,
+	/// process the directories described in this TOML file instead of a single one
+	///
+	/// Each `[[directory]]` table has a `path` and takes the same settings as the options
+	/// that apply to a single directory, such as `size`, `exclude`, `time`, or `quarantine`.
+	/// Those options can't be given on the command line as well.
+	#[argh(option, short = 'c')]
+	config: Option<PathBuf>,
+	#[argh(subcommand)]
+	command: Option<Command>,
+	/// the directory to process, or several directories to limit together
+	///
+	/// Several directories share the limits as one pool, and the least-recently used files are deleted from whichever of them they are in.
+	/// Percentages and `--min-free` refer to the filesystem containing the first directory.
+	#[argh(positional)]
+	directories: Vec<PathBuf>,
+}
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand)]
+enum Command {
+	Restore(Restore),
+}
+
+/// Move quarantined files back into the directory.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "restore")]
+struct Restore {
+	/// the quarantine that was given to `--quarantine`
+	#[argh(option)]
+	quarantine: PathBuf,
+	/// what to do when a file can't be restored: skip or abort (default skip)
+	///
+	/// Files that exist again in the directory are never overwritten.
+	#[argh(option, default = "OnError::Skip")]
+	on_error: OnError,
+	/// the directory to restore the files into
 	#[argh(positional)]
 	directory: PathBuf,
 }
 
-fn counted_file_type(ty: FileType) -> bool {
-	ty.is_file() || ty.is_symlink()
+/// The limits from the arguments, some of which depend on the filesystem at run time.
+#[derive(Clone, Copy)]
+struct Limits {
+	/// The high and low watermarks.
+	size: Option<(Amount, Amount)>,
+	min_free: Option<Amount>,
+	max_files: Option<u64>,
+	/// The limit on each child and its depth.
+	per_child: Option<(Amount, usize)>,
 }
 
-fn main() {
-	let Args {
-		dry_run,
-		keep_parents,
-		size: ByteSize(goal),
-		directory,
-	} = argh::from_env();
+impl Args {
+	/// Check the options that apply to the whole invocation rather than to each directory.
+	fn check(&self) -> Result<(), &'static str> {
+		if !self.watch && self.metrics_listen.is_some() {
+			return Err("`--metrics-listen` can only be used with `--watch`");
+		}
+		if !self.watch && self.debounce.is_some() {
+			return Err("`--debounce` can only be used with `--watch`");
+		}
+		if self.watch && self.format == Format::Json {
+			return Err("`--format json` can't be used with `--watch` since it prints everything at the end, use `ndjson` instead");
+		}
+		if self.config.is_none() {
+			return Ok(());
+		}
+		if !self.directories.is_empty() {
+			return Err("directories can't be given with `--config`, which lists them");
+		}
+		if self.watch {
+			return Err("`--watch` can't be used with `--config`");
+		}
+		let per_directory = [
+			self.size.is_some(),
+			self.high.is_some(),
+			self.low.is_some(),
+			self.min_free.is_some(),
+			self.max_files.is_some(),
+			self.per_child.is_some(),
+			self.child_depth.is_some(),
+			self.time.is_some(),
+			self.usage.is_some(),
+			self.on_error.is_some(),
+			!self.include.is_empty(),
+			!self.exclude.is_empty(),
+			self.count_deletable_only,
+			self.keep_parents,
+			self.min_age.is_some(),
+			self.skip_open,
+			self.respect_locks,
+			self.trash,
+			self.quarantine.is_some(),
+			self.grace.is_some(),
+			self.archive.is_some(),
+		];
+		if per_directory.into_iter().any(|given| given) {
+			return Err("options that apply to a directory can't be given with `--config`, set them in the configuration file instead");
+		}
+		Ok(())
+	}
 
-	let mut size: u64 = WalkDir::new(&directory)
-		.min_depth(1)
-		.into_iter()
-		.map(|entry| entry.unwrap_io("walking", &directory))
-		.filter(|entry| counted_file_type(entry.file_type()))
-		.map(|entry| {
-			entry
-				.metadata()
-				.unwrap_io_lazy("getting metadata of", || entry.path())
-				.size()
+	/// The single job described by the options, taking them out of `self`.
+	fn job(&mut self) -> Job {
+		let defaults = Job::default();
+		Job {
+			paths: std::mem::take(&mut self.directories),
+			size: self.size,
+			high: self.high,
+			low: self.low,
+			min_free: self.min_free,
+			max_files: self.max_files,
+			per_child: self.per_child,
+			child_depth: self.child_depth.unwrap_or(defaults.child_depth),
+			time: self.time.unwrap_or(defaults.time),
+			usage: self.usage,
+			on_error: self.on_error.unwrap_or(defaults.on_error),
+			include: std::mem::take(&mut self.include),
+			exclude: std::mem::take(&mut self.exclude),
+			count_deletable_only: self.count_deletable_only,
+			keep_parents: self.keep_parents,
+			min_age: self.min_age.map_or(defaults.min_age, Into::into),
+			skip_open: self.skip_open,
+			respect_locks: self.respect_locks,
+			trash: self.trash,
+			quarantine: self.quarantine.take(),
+			grace: self.grace.map_or(defaults.grace, Into::into),
+			archive: self.archive.take(),
+		}
+	}
+}
+
+impl Job {
+	fn limits(&self) -> Result<Limits, String> {
+		let size = match (self.size, self.high, self.low) {
+			(None, None, None)
+				if self.min_free.is_some()
+					|| self.max_files.is_some()
+					|| self.per_child.is_some() =>
+			{
+				None
+			}
+			(Some(size), None, None) => Some((size, size)),
+			(None, Some(high), Some(low)) => {
+				// Mixed absolute and relative watermarks can only be compared once resolved.
+				let inverted = match (high, low) {
+					(Amount::Bytes(high), Amount::Bytes(low)) => low > high,
+					(Amount::Percent(high), Amount::Percent(low)) => low > high,
+					_ => false,
+				};
+				if inverted {
+					return Err("`--low` must not be greater than `--high`".into());
+				}
+				Some((high, low))
+			}
+			_ => {
+				return Err(
+					"either `--size`, both `--high` and `--low`, `--min-free`, `--max-files`, or `--per-child` must be given".into(),
+				)
+			}
+		};
+		let per_child = match self.per_child {
+			Some(_) if self.child_depth == 0 => {
+				return Err("`--child-depth` must be at least 1".into());
+			}
+			Some(per_child) => Some((per_child, self.child_depth)),
+			None if self.child_depth != Job::default().child_depth => {
+				return Err("`--child-depth` can only be used with `--per-child`".into());
+			}
+			None => None,
+		};
+
+		let disposals = [
+			self.trash,
+			self.quarantine.is_some(),
+			self.archive.is_some(),
+		];
+		if disposals.into_iter().filter(|&given| given).count() > 1 {
+			return Err("only one of `--trash`, `--quarantine`, and `--archive` can be given".into());
+		}
+		if (self.trash || self.quarantine.is_some()) && self.min_free.is_some() {
+			return Err("`--trash` and `--quarantine` don't free space on the filesystem right away, so they can't be used with `--min-free`".into());
+		}
+		if self.min_free.is_some() && self.usage == Some(Usage::Apparent) {
+			return Err("`--min-free` is about the space allocated on the filesystem, so it can't be used with `--usage apparent`".into());
+		}
+		if self.quarantine.is_none() && self.grace != Job::default().grace {
+			return Err("`--grace` can only be used with `--quarantine`".into());
+		}
+		if self.quarantine.is_some() && self.paths.len() > 1 {
+			return Err("`--quarantine` can only be used with a single directory, which `sau restore` moves the files back into".into());
+		}
+		// Files in both of two nested directories would be counted twice.
+		let canonical: Vec<PathBuf> = self
+			.paths
+			.iter()
+			.map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+			.collect();
+		for (index, path) in canonical.iter().enumerate() {
+			if let Some(other) = canonical[index + 1..]
+				.iter()
+				.find(|other| path.starts_with(other) || other.starts_with(path))
+			{
+				return Err(format!("the directories {path:?} and {other:?} overlap"));
+			}
+		}
+		// Nothing is created until there is something to dispose of.
+		self
+			.disposal()
+			.check(&self.paths)
+			.map_err(|error| error.to_string())?;
+
+		Ok(Limits {
+			size,
+			min_free: self.min_free,
+			max_files: self.max_files,
+			per_child,
 		})
-		.sum();
+	}
 
-	eprintln!("initial size is {}", ByteSize(size));
-	if size <= goal {
-		eprintln!("no need to delete anything, exiting");
-		return;
+	fn options(&self) -> Result<Options, String> {
+		let mut filter = Filter::new(&self.include, &self.exclude)
+			.map_err(|error| format!("invalid pattern: {error}"))?;
+		filter.count_excluded = !self.count_deletable_only;
+		Ok(Options {
+			time: self.time,
+			usage: self.usage(),
+			keep_parents: self.keep_parents,
+			on_error: self.on_error,
+			filter,
+			min_age: self.min_age,
+			skip_open: self.skip_open,
+			respect_locks: self.respect_locks,
+			disposal: self.disposal(),
+		})
+	}
+
+	/// Deleting a sparse file frees less space on the filesystem than its length,
+	/// so with `--min-free` files are measured by the blocks they use.
+	fn usage(&self) -> Usage {
+		self.usage.unwrap_or(if self.min_free.is_some() {
+			Usage::Blocks
+		} else {
+			Options::default().usage
+		})
 	}
 
-	let action = if dry_run { "would delete" } else { "deleting" };
+	fn disposal(&self) -> Disposal {
+		match (self.trash, &self.quarantine, &self.archive) {
+			(true, ..) => Disposal::Trash,
+			(_, Some(quarantine), _) => Disposal::Quarantine(Quarantine::new(quarantine.clone())),
+			(.., Some(archive)) => Disposal::Archive(archive.clone()),
+			_ => Disposal::Delete,
+		}
+	}
+}
 
-	for file in WalkDir::new(&directory).min_depth(1).sort_by_key(|entry| {
-		entry
-			.metadata()
-			.unwrap_io_lazy("getting metadata on", || entry.path())
-			.mtime()
-	}) {
-		let file = file.unwrap_io("reading", &directory);
+impl Limits {
+	fn resolve(self, directory: &Path) -> Result<Goal, Error> {
+		let relative = [
+			self.size.map(|(high, _)| high),
+			self.size.map(|(_, low)| low),
+			self.per_child.map(|(per_child, _)| per_child),
+		]
+		.into_iter()
+		.flatten()
+		.any(|amount| matches!(amount, Amount::Percent(_)));
+		let filesystem = if relative || self.min_free.is_some() {
+			Some(Filesystem::containing(directory)?)
+		} else {
+			None
+		};
+		let capacity = filesystem.map_or(0, |filesystem| filesystem.capacity);
 
-		if file
-			.metadata()
-			.unwrap_io_lazy("getting metadata on", || file.path())
-			.is_dir()
-		{
-			continue;
+		let mut goal = match self.size {
+			Some((high, low)) => {
+				let high = high.resolve(capacity);
+				Goal::watermarks(high, low.resolve(capacity).min(high))
+			}
+			None => Goal::reclaim(0),
+		};
+		if let Some(max_files) = self.max_files {
+			goal = goal.and_max_files(max_files);
+		}
+		if let Some((per_child, depth)) = self.per_child {
+			goal = goal.and_per_child(per_child.resolve(capacity), depth);
 		}
 
-		let path = file.path();
-		size -= file
-			.metadata()
-			.unwrap_io("getting metadata of", path)
-			.size();
-		eprintln!("{action} {path:?}, size is now {}", ByteSize(size));
-		if !dry_run {
-			std::fs::remove_file(path).unwrap_io("deleting", path);
+		let (Some(min_free), Some(filesystem)) = (self.min_free, filesystem) else {
+			return Ok(goal);
+		};
+		let min_free = min_free.resolve(capacity);
+		let deficit = min_free.saturating_sub(filesystem.available);
+		if deficit > 0 {
+			eprintln!(
+				"only {} is available but {} should be, so at least {} will be freed",
+				ByteSize(filesystem.available),
+				ByteSize(min_free),
+				ByteSize(deficit),
+			);
+		}
+		Ok(goal.and_reclaim(deficit))
+	}
+}
 
-			if !keep_parents {
-				remove_empty_ancestors(path, &directory);
+fn main() -> ExitCode {
+	let mut args: Args = argh::from_env();
+	if let Some(Command::Restore(restore)) = args.command {
+		let quarantine = Quarantine::new(restore.quarantine);
+		return match quarantine.restore(&restore.directory, restore.on_error, |path| {
+			eprintln!("restored {path:?}");
+		}) {
+			Ok(skipped) => exit_code(&skipped, false),
+			Err(error) => {
+				eprintln!("{error}, aborting");
+				ExitCode::FAILURE
+			}
+		};
+	}
+	if let Err(message) = args.check() {
+		eprintln!("{message}");
+		return ExitCode::FAILURE;
+	}
+	let configured = args.config.is_some();
+	let jobs = if let Some(config) = &args.config {
+		match config::load(config) {
+			Ok(jobs) => jobs,
+			Err(message) => {
+				eprintln!("{message}");
+				return ExitCode::FAILURE;
 			}
 		}
+	} else if !args.directories.is_empty() {
+		vec![args.job()]
+	} else {
+		eprintln!("the directory must be given");
+		return ExitCode::FAILURE;
+	};
 
-		if size <= goal {
-			eprintln!("size is now under limit, exiting");
-			break;
+	// Every directory is checked before any of them is touched.
+	let mut prepared = Vec::new();
+	for job in jobs {
+		match job.limits().and_then(|limits| Ok((limits, job.options()?))) {
+			Ok((limits, options)) => prepared.push((job, limits, options)),
+			Err(message) if configured => {
+				eprintln!("{}: {message}", describe(&job.paths));
+				return ExitCode::FAILURE;
+			}
+			Err(message) => {
+				eprintln!("{message}");
+				return ExitCode::FAILURE;
+			}
+		}
+	}
+
+	let mut metrics = Metrics::new(args.metrics_file.clone());
+	if let Some(address) = args.metrics_listen {
+		if let Err(error) = metrics.serve(address) {
+			eprintln!("error listening on {address}: {error}");
+			return ExitCode::FAILURE;
+		}
+	}
+
+	let mut reporter = Reporter::new(args.format);
+	let mut skipped = Vec::new();
+	let mut summaries = Vec::new();
+	for (job, limits, options) in prepared {
+		let directory = describe(&job.paths);
+		if configured {
+			eprintln!("processing {directory}");
+		}
+		match run(&args, job, limits, options, &mut reporter, &mut metrics) {
+			Ok(mut summary) => {
+				skipped.append(&mut summary.skipped);
+				summaries.push((directory, Some(summary)));
+			}
+			Err(error) => {
+				// An abort only stops the directory it happened in.
+				if configured {
+					eprintln!("{error}, aborting this directory");
+				} else {
+					eprintln!("{error}, aborting");
+				}
+				reporter.record(&Record::error(&error, true));
+				summaries.push((directory, None));
+			}
 		}
 	}
+	reporter.finish();
+
+	if configured {
+		report_summary(&summaries);
+	}
+	let aborted = summaries.iter().any(|(_, summary)| summary.is_none());
+	exit_code(&skipped, aborted)
 }
 
-fn remove_empty_ancestors(path: &Path, within: &Path) {
-	for ancestor in path.ancestors().skip(1) {
-		if !ancestor.starts_with(within) {
-			break;
+/// Summarize the errors that were skipped.
+fn exit_code(skipped: &[Error], aborted: bool) -> ExitCode {
+	if !skipped.is_empty() {
+		eprintln!(
+			"{} errors occurred, the affected files were skipped:",
+			skipped.len()
+		);
+		for error in skipped {
+			eprintln!("  {error}");
 		}
+	}
+	if aborted {
+		ExitCode::FAILURE
+	} else if skipped.is_empty() {
+		ExitCode::SUCCESS
+	} else {
+		ExitCode::from(2)
+	}
+}
+
+/// What a run did to a directory.
+struct Summary {
+	skipped: Vec<Error>,
+	freed: u64,
+	size: u64,
+	files: u64,
+	goal_met: bool,
+}
+
+/// The directories of a job, for messages.
+fn describe(directories: &[PathBuf]) -> String {
+	let quoted: Vec<String> = directories
+		.iter()
+		.map(|directory| format!("{directory:?}"))
+		.collect();
+	quoted.join(" and ")
+}
+
+/// Report the outcome for every directory of the configuration file, or `None` for those that were aborted.
+fn report_summary(summaries: &[(String, Option<Summary>)]) {
+	let freed = summaries
+		.iter()
+		.filter_map(|(_, summary)| summary.as_ref())
+		.map(|summary| summary.freed)
+		.sum();
+	eprintln!(
+		"freed {} in total from {} directories:",
+		ByteSize(freed),
+		summaries.len()
+	);
+	for (directory, summary) in summaries {
+		let Some(summary) = summary else {
+			eprintln!("  {directory}: aborted");
+			continue;
+		};
+		eprintln!(
+			"  {directory}: freed {}, now {} in {} files{}",
+			ByteSize(summary.freed),
+			ByteSize(summary.size),
+			summary.files,
+			if summary.goal_met {
+				""
+			} else {
+				", still over the limit"
+			}
+		);
+	}
+}
 
-		// This approach suits this subroutine because this is a secondary, non-critical part of the functionality.
-		// The error case includes "directory not empty", which is a termination condition regardless.
-		if std::fs::remove_dir(ancestor).is_err() {
-			break;
+/// How long watch mode waits after a change for further changes by default.
+const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// How long watch mode waits for a change before checking the goal again anyway, when that could make a difference.
+const RECHECK_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Process the directories of `job`, or keep watching them with `--watch`.
+fn run(
+	args: &Args,
+	job: Job,
+	limits: Limits,
+	options: Options,
+	reporter: &mut Reporter,
+	metrics: &mut Metrics,
+) -> Result<Summary, Error> {
+	let Args {
+		dry_run,
+		watch,
+		debounce,
+		..
+	} = *args;
+	let Job {
+		paths: directories,
+		on_error,
+		quarantine,
+		grace,
+		min_age,
+		..
+	} = job;
+	let quarantine = quarantine.map(Quarantine::new);
+	let purge = |reporter: &mut Reporter| -> Result<Vec<Error>, Error> {
+		let Some(quarantine) = &quarantine else {
+			return Ok(Vec::new());
+		};
+		if dry_run {
+			return Ok(Vec::new());
+		}
+		let skipped = quarantine.purge(grace, on_error, |batch| {
+			eprintln!("deleted quarantined batch {batch:?}");
+			reporter.record(&Record::PurgedBatch {
+				path: report::path(batch),
+			});
+		})?;
+		for error in &skipped {
+			reporter.record(&Record::error(error, false));
 		}
-		eprintln!("deleted empty ancestor {path:?}");
+		Ok(skipped)
+	};
+
+	let mut finish = |mut plan: Plan,
+	                  mut skipped: Vec<Error>,
+	                  started: Instant,
+	                  reporter: &mut Reporter|
+	 -> Result<Summary, Error> {
+		let outcome = evict(&mut plan, dry_run, reporter)?;
+		skipped.extend(outcome.errors);
+		metrics.record(&Run {
+			plan: &plan,
+			size: outcome.size,
+			files: outcome.files,
+			errors: skipped.len(),
+			duration: started.elapsed(),
+		});
+		Ok(Summary {
+			skipped,
+			freed: plan.initial_size.saturating_sub(outcome.size),
+			size: outcome.size,
+			files: outcome.files,
+			goal_met: goal_met(&plan, outcome.size, outcome.files),
+		})
+	};
+
+	if watch {
+		let debounce = debounce.map_or(DEFAULT_DEBOUNCE, Into::into);
+		// Without any change in the directories, files still age, quarantined batches still expire,
+		// and the rest of the filesystem still fills up.
+		let recheck = !min_age.is_zero() || quarantine.is_some() || limits.min_free.is_some();
+		let mut watcher = Watcher::new_pooled(&directories, options)?;
+		loop {
+			let started = Instant::now();
+			let skipped = purge(reporter)?;
+			let plan = watcher.plan(limits.resolve(&directories[0])?)?;
+			let summary = finish(plan, skipped, started, reporter)?;
+			// There is no end of the run to summarize at, so report errors as they happen.
+			for error in summary.skipped {
+				eprintln!("{error}, skipping");
+			}
+			if recheck {
+				watcher.wait_timeout(debounce, RECHECK_INTERVAL)?;
+			} else {
+				watcher.wait(debounce)?;
+			}
+		}
+	}
+
+	let started = Instant::now();
+	let skipped = purge(reporter)?;
+	let goal = limits.resolve(&directories[0])?;
+	let plan = Plan::compute_pooled(directories, goal, options)?;
+	finish(plan, skipped, started, reporter)
+}
+
+/// The outcome's errors include those that were skipped while computing the plan.
+fn evict(plan: &mut Plan, dry_run: bool, reporter: &mut Reporter) -> Result<Outcome, Error> {
+	let mut skipped = std::mem::take(&mut plan.errors);
+
+	if plan.birth_time_fallback {
+		eprintln!("warning: birth times are not available for some files, using their modification times instead");
+	}
+
+	eprintln!(
+		"initial size is {} in {} files",
+		ByteSize(plan.initial_size),
+		plan.initial_files
+	);
+	reporter.record(&Record::Plan {
+		directory: report::path(&plan.directories[0]),
+		directories: plan
+			.directories
+			.iter()
+			.map(|directory| report::path(directory))
+			.collect(),
+		dry_run,
+		initial_size: plan.initial_size,
+		initial_files: plan.initial_files,
+		goal: plan.goal.into(),
+	});
+	for error in &skipped {
+		reporter.record(&Record::error(error, false));
+	}
+	if plan.victims.is_empty() && goal_met(plan, plan.initial_size, plan.initial_files) {
+		if plan.initial_size > plan.goal.low && plan.initial_files <= plan.goal.max_files {
+			eprintln!("size is under the high watermark, no need to delete anything");
+		} else {
+			eprintln!("no need to delete anything");
+		}
+		report_result(plan, plan.initial_size, plan.initial_files, reporter);
+		return Ok(Outcome {
+			size: plan.initial_size,
+			files: plan.initial_files,
+			errors: skipped,
+		});
+	}
+	let (action, message) = match (&plan.options.disposal, dry_run) {
+		(Disposal::Trash, false) => ("trash", "trashing"),
+		(Disposal::Trash, true) => ("trash", "would trash"),
+		(Disposal::Quarantine(_), false) => ("quarantine", "quarantining"),
+		(Disposal::Quarantine(_), true) => ("quarantine", "would quarantine"),
+		(Disposal::Archive(_), false) => ("archive", "archiving"),
+		(Disposal::Archive(_), true) => ("archive", "would archive"),
+		(_, false) => ("delete", "deleting"),
+		(_, true) => ("delete", "would delete"),
+	};
+	let (size, files) = if dry_run {
+		for victim in &plan.victims {
+			report_victim(victim, action, message, reporter);
+		}
+		(plan.projected_size, plan.projected_files)
+	} else {
+		let outcome = plan.execute(|event| report_event(event, action, message, reporter))?;
+		skipped.extend(outcome.errors);
+		(outcome.size, outcome.files)
+	};
+	report_status(plan, size, files);
+	report_result(plan, size, files, reporter);
+
+	Ok(Outcome {
+		size,
+		files,
+		errors: skipped,
+	})
+}
+
+/// `action` names the disposal in records and `message` describes it in messages.
+fn report_victim(victim: &Victim, action: &'static str, message: &str, reporter: &mut Reporter) {
+	let Victim {
+		path,
+		last_link,
+		size_after,
+		..
+	} = victim;
+	if *last_link {
+		eprintln!("{message} {path:?}, size is now {}", ByteSize(*size_after));
+	} else {
+		eprintln!(
+			"{message} {path:?}, size is still {} because other hard links remain",
+			ByteSize(*size_after)
+		);
+	}
+	reporter.record(&Record::victim(victim, action));
+}
+
+fn report_event(event: Event<'_>, action: &'static str, message: &str, reporter: &mut Reporter) {
+	match event {
+		Event::Deleting(victim) => report_victim(victim, action, message, reporter),
+		Event::Vanished(victim) => {
+			eprintln!(
+				"{:?} disappeared on its own, counting it as freed",
+				victim.path
+			);
+			reporter.record(&Record::Vanished {
+				path: report::path(&victim.path),
+			});
+		}
+		Event::Changed(victim) => {
+			eprintln!(
+				"{:?} was modified since planning, leaving it alone",
+				victim.path
+			);
+			reporter.record(&Record::Changed {
+				path: report::path(&victim.path),
+			});
+		}
+		Event::Locked(victim) => {
+			eprintln!(
+				"{:?} is locked by another process, leaving it alone",
+				victim.path
+			);
+			reporter.record(&Record::Locked {
+				path: report::path(&victim.path),
+			});
+		}
+		Event::RemovedDirectory(path) => {
+			eprintln!("deleted empty ancestor {path:?}");
+			reporter.record(&Record::RemovedDirectory {
+				path: report::path(path),
+			});
+		}
+		Event::Skipped(error) => {
+			eprintln!("{error}, skipping");
+			reporter.record(&Record::error(error, false));
+		}
+		_ => {}
+	}
+}
+
+fn report_result(plan: &Plan, size: u64, files: u64, reporter: &mut Reporter) {
+	reporter.record(&Record::Result {
+		directory: report::path(&plan.directories[0]),
+		size,
+		files,
+		goal_met: goal_met(plan, size, files),
+		too_young: plan.too_young,
+		in_use: plan.in_use,
+		protected: plan.protected,
+		children_over: plan.children_over,
+	});
+}
+
+/// Whether the goal is met with this size and number of files after deleting the victims.
+fn goal_met(plan: &Plan, size: u64, files: u64) -> bool {
+	plan.initial_size.saturating_sub(size) >= plan.goal.reclaim
+		&& files <= plan.goal.max_files
+		&& plan.children_over == 0
+		&& size <= plan.goal.target(plan.initial_size)
+}
+
+/// Report whether the goal was met, given the size and number of files after deleting the victims.
+fn report_status(plan: &Plan, size: u64, files: u64) {
+	let target = plan.goal.target(plan.initial_size);
+	let freed = plan.initial_size.saturating_sub(size);
+	if freed < plan.goal.reclaim {
+		eprintln!(
+			"only {} could be freed from the directory, which is short of the {} needed",
+			ByteSize(freed),
+			ByteSize(plan.goal.reclaim)
+		);
+	} else if files > plan.goal.max_files {
+		eprintln!(
+			"there are still {files} files which is over the limit of {}",
+			plan.goal.max_files
+		);
+	} else if size <= target {
+		eprintln!("size is now under limit");
+	} else {
+		eprintln!(
+			"size is still {} which is over the limit of {}",
+			ByteSize(size),
+			ByteSize(target)
+		);
+	}
+	if plan.children_over > 0 {
+		eprintln!(
+			"{} child directories are still over the limit of {} each",
+			plan.children_over,
+			ByteSize(plan.goal.per_child)
+		);
+	}
+	if plan.too_young > 0 {
+		eprintln!(
+			"{} files were not deleted because they are younger than the minimum age of {}",
+			plan.too_young,
+			humantime::format_duration(plan.options.min_age)
+		);
+	}
+	if plan.in_use > 0 {
+		eprintln!(
+			"{} files were not deleted because a process has them open",
+			plan.in_use
+		);
+	}
+	if plan.protected > 0 {
+		eprintln!(
+			"{} files were not deleted because they are excluded or protected by rules",
+			plan.protected
+		);
 	}
 }
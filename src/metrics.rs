@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead as _, BufReader, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sau::Plan;
+
+/// Metrics about the runs, in the Prometheus text format.
+///
+/// They are written to the textfile collector's directory of `node_exporter`, served over HTTP, or both.
+pub struct Metrics {
+	file: Option<PathBuf>,
+	/// The latest rendering, shared with the HTTP server.
+	rendered: Arc<Mutex<String>>,
+	/// The metrics of each directory, labelled with its path.
+	directories: BTreeMap<String, Series>,
+}
+
+/// The metrics of a single directory.
+#[derive(Default)]
+struct Series {
+	runs: u64,
+	deleted_files: u64,
+	freed_bytes: u64,
+	errors: u64,
+	/// The value of each metric after the last run, in the order of [`METRICS`].
+	values: [Option<f64>; 14],
+}
+
+/// The name, type, and help text of each metric, in the order of [`Series::values`].
+const METRICS: [(&str, &str, &str); 14] = [
+	(
+		"sau_size_before_bytes",
+		"gauge",
+		"Size of the directory before the last run.",
+	),
+	(
+		"sau_size_after_bytes",
+		"gauge",
+		"Size of the directory after the last run.",
+	),
+	(
+		"sau_files_before",
+		"gauge",
+		"Number of files in the directory before the last run.",
+	),
+	(
+		"sau_files_after",
+		"gauge",
+		"Number of files in the directory after the last run.",
+	),
+	(
+		"sau_deleted_files",
+		"gauge",
+		"Number of files deleted by the last run.",
+	),
+	(
+		"sau_freed_bytes",
+		"gauge",
+		"Space freed from the directory by the last run.",
+	),
+	(
+		"sau_errors",
+		"gauge",
+		"Number of errors skipped by the last run.",
+	),
+	(
+		"sau_run_duration_seconds",
+		"gauge",
+		"How long the last run took.",
+	),
+	(
+		"sau_oldest_file_age_seconds",
+		"gauge",
+		"Age of the oldest file left in the directory by the last run, by the selected timestamp.",
+	),
+	(
+		"sau_last_run_timestamp_seconds",
+		"gauge",
+		"When the last run finished, in seconds since the Unix epoch.",
+	),
+	(
+		"sau_runs_total",
+		"counter",
+		"Number of runs since sau started.",
+	),
+	(
+		"sau_deleted_files_total",
+		"counter",
+		"Number of files deleted since sau started.",
+	),
+	(
+		"sau_freed_bytes_total",
+		"counter",
+		"Space freed from the directory since sau started.",
+	),
+	(
+		"sau_errors_total",
+		"counter",
+		"Number of errors skipped since sau started.",
+	),
+];
+
+/// What happened in a single run, or a single pass in watch mode.
+pub struct Run<'a> {
+	pub plan: &'a Plan,
+	pub size: u64,
+	pub files: u64,
+	pub errors: usize,
+	pub duration: Duration,
+}
+
+impl Metrics {
+	/// Write the metrics to `file` after every run, if given.
+	pub fn new(file: Option<PathBuf>) -> Self {
+		Self {
+			file,
+			rendered: Arc::default(),
+			directories: BTreeMap::new(),
+		}
+	}
+
+	/// Serve the metrics over HTTP on `address` from a background thread.
+	pub fn serve(&self, address: SocketAddr) -> io::Result<()> {
+		let listener = TcpListener::bind(address)?;
+		let rendered = Arc::clone(&self.rendered);
+		std::thread::spawn(move || {
+			for stream in listener.incoming() {
+				// A misbehaving client only affects its own request.
+				let _ = stream.and_then(|stream| respond(&stream, &rendered));
+			}
+		});
+		Ok(())
+	}
+
+	/// Update the metrics of the run's directory with its outcome, then write them out.
+	pub fn record(&mut self, run: &Run<'_>) {
+		// A pool of directories is labelled with the first of them.
+		let directory = escape_label(&run.plan.directories[0].to_string_lossy());
+		self.directories.entry(directory).or_default().record(run);
+
+		let mut rendered = String::new();
+		for (index, (name, kind, help)) in METRICS.into_iter().enumerate() {
+			// Metrics without a value, like the age of the oldest file in an empty directory, are left out.
+			let mut values = self
+				.directories
+				.iter()
+				.filter_map(|(directory, series)| Some((directory, series.values[index]?)))
+				.peekable();
+			if values.peek().is_none() {
+				continue;
+			}
+			let _ = writeln!(rendered, "# HELP {name} {help}");
+			let _ = writeln!(rendered, "# TYPE {name} {kind}");
+			for (directory, value) in values {
+				let _ = writeln!(rendered, "{name}{{directory=\"{directory}\"}} {value}");
+			}
+		}
+
+		if let Some(file) = &self.file {
+			if let Err(error) = write_atomically(file, &rendered) {
+				eprintln!("error writing metrics to {file:?}: {error}");
+			}
+		}
+		*self.rendered.lock().unwrap_or_else(PoisonError::into_inner) = rendered;
+	}
+}
+
+impl Series {
+	#[allow(clippy::cast_precision_loss)]
+	fn record(&mut self, run: &Run<'_>) {
+		let plan = run.plan;
+		let deleted_files = plan.initial_files.saturating_sub(run.files);
+		let freed_bytes = plan.initial_size.saturating_sub(run.size);
+		self.runs += 1;
+		self.deleted_files += deleted_files;
+		self.freed_bytes += freed_bytes;
+		self.errors += run.errors as u64;
+
+		let now = SystemTime::now();
+		let age = |time: SystemTime| now.duration_since(time).unwrap_or(Duration::ZERO);
+		self.values = [
+			Some(plan.initial_size as f64),
+			Some(run.size as f64),
+			Some(plan.initial_files as f64),
+			Some(run.files as f64),
+			Some(deleted_files as f64),
+			Some(freed_bytes as f64),
+			Some(run.errors as f64),
+			Some(run.duration.as_secs_f64()),
+			plan.oldest_kept.map(|oldest| age(oldest).as_secs_f64()),
+			Some(age(UNIX_EPOCH).as_secs_f64()),
+			Some(self.runs as f64),
+			Some(self.deleted_files as f64),
+			Some(self.freed_bytes as f64),
+			Some(self.errors as f64),
+		];
+	}
+}
+
+/// Write to a temporary file next to `path` and rename it over `path`,
+/// so that the textfile collector never reads a partially written file.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+	let mut temporary = path.as_os_str().to_owned();
+	// The textfile collector only reads files ending in `.prom`, so it ignores this one.
+	temporary.push(".tmp");
+	let temporary = PathBuf::from(temporary);
+	std::fs::write(&temporary, contents)?;
+	std::fs::rename(&temporary, path)
+}
+
+fn escape_label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+/// Answer any request with the latest metrics.
+fn respond(mut stream: &TcpStream, rendered: &Mutex<String>) -> io::Result<()> {
+	// Slow clients must not block the others forever.
+	stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+	let mut reader = BufReader::new(stream);
+	let mut line = String::new();
+	// Read the request line and the headers up to the blank line that ends them.
+	loop {
+		line.clear();
+		if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+			break;
+		}
+	}
+
+	let body = rendered
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.clone();
+	write!(
+		stream,
+		"HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		body.len()
+	)
+}
@@ -0,0 +1,38 @@
+use std::collections::HashSet;
+use std::os::unix::fs::MetadataExt as _;
+use std::path::Path;
+
+use crate::Error;
+
+/// The device and inode numbers of the files that some process has open.
+///
+/// Only the processes whose file descriptors are visible to us are seen,
+/// so running as an unprivileged user misses the files that other users have open.
+pub(crate) fn open_files() -> Result<HashSet<(u64, u64)>, Error> {
+	let proc = Path::new("/proc");
+	let mut open = HashSet::new();
+	for process in
+		std::fs::read_dir(proc).map_err(|error| Error::OpenFiles(proc.to_owned(), error))?
+	{
+		let process = process.map_err(|error| Error::OpenFiles(proc.to_owned(), error))?;
+		let is_pid = process
+			.file_name()
+			.to_str()
+			.is_some_and(|name| name.bytes().all(|byte| byte.is_ascii_digit()));
+		if !is_pid {
+			continue;
+		}
+
+		// Processes come and go, and those of other users can't be inspected, so these errors are expected.
+		let Ok(descriptors) = std::fs::read_dir(process.path().join("fd")) else {
+			continue;
+		};
+		for descriptor in descriptors.flatten() {
+			// Following the link gets the metadata of the open file, even if it was renamed.
+			if let Ok(metadata) = std::fs::metadata(descriptor.path()) {
+				open.insert((metadata.dev(), metadata.ino()));
+			}
+		}
+	}
+	Ok(open)
+}
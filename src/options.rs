@@ -0,0 +1,245 @@
+use std::fs::Metadata;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::IoResultExt as _;
+use crate::{Error, Filter, OnError, Quarantine};
+
+/// Options that control how a [`Plan`](crate::Plan) is computed and executed.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The timestamp used to order files.
+	pub time: TimeKey,
+	/// How file sizes are measured.
+	pub usage: Usage,
+	/// Don't delete parent directories that become empty.
+	pub keep_parents: bool,
+	/// What to do when an I/O error affects a single file or directory.
+	pub on_error: OnError,
+	/// Which files may be deleted.
+	pub filter: Filter,
+	/// Files whose timestamp is more recent than this are never deleted,
+	/// since they may still be being written.
+	pub min_age: Duration,
+	/// Never delete files that a process has open, since that frees no space until they are closed.
+	///
+	/// This is only supported on Linux, where open files are found in `/proc`.
+	pub skip_open: bool,
+	/// Take an exclusive `flock` on each file before deleting it, and leave it alone if another process holds a lock on it.
+	pub respect_locks: bool,
+	/// What to do with the files that are chosen for deletion.
+	pub disposal: Disposal,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Self {
+			time: TimeKey::Mtime,
+			usage: Usage::Apparent,
+			keep_parents: false,
+			on_error: OnError::Skip,
+			filter: Filter::default(),
+			min_age: Duration::ZERO,
+			skip_open: false,
+			respect_locks: false,
+			disposal: Disposal::Delete,
+		}
+	}
+}
+
+/// What to do with the files that are chosen for deletion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Disposal {
+	/// Delete them.
+	Delete,
+	/// Move them to the trash, following the freedesktop.org Trash specification, so that they can be restored.
+	///
+	/// This only frees space in the directory, not on the filesystem.
+	Trash,
+	/// Move them into a quarantine, from which they are deleted for good once a grace period has elapsed.
+	///
+	/// This only frees space on the filesystem once the quarantine is [purged](Quarantine::purge).
+	/// Files are kept at their paths relative to the directory they were in,
+	/// so a quarantine should only be used for a single directory.
+	Quarantine(Quarantine),
+	/// Append them to a zstd-compressed tar archive at this path, then delete them.
+	///
+	/// The files are named by their paths relative to the directory they were in,
+	/// or by their absolute paths without the leading `/` for a pool of directories.
+	/// Each file is written as its own zstd frame holding a tar entry without an end-of-archive marker,
+	/// so that an interrupted run can't corrupt what was archived before it,
+	/// and the decompressed frames form a single tar stream that `tar -xf` reads in full.
+	Archive(PathBuf),
+}
+
+impl Disposal {
+	/// Make sure that the quarantine or archive is not within any of `directories`,
+	/// where it would count towards their size, without creating it.
+	///
+	/// [`Plan::execute`](crate::Plan::execute) checks this before disposing of anything.
+	///
+	/// # Errors
+	///
+	/// If it is within one of them, or if the paths can't be resolved.
+	pub fn check(&self, directories: &[PathBuf]) -> Result<(), Error> {
+		let (path, variant, name): (&Path, fn(PathBuf, io::Error) -> Error, _) = match self {
+			Self::Quarantine(quarantine) => (&quarantine.directory, Error::Quarantine, "quarantine"),
+			Self::Archive(path) => (path, Error::Archive, "archive"),
+			Self::Delete | Self::Trash => return Ok(()),
+		};
+		let resolved = resolve(path).or_error(variant, path)?;
+		for directory in directories {
+			if resolved.starts_with(directory.canonicalize().or_error(variant, directory)?) {
+				let message = format!("the {name} is within the directory {directory:?} being limited");
+				return Err(variant(
+					path.to_owned(),
+					io::Error::new(ErrorKind::InvalidInput, message),
+				));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Resolve `path` like [`Path::canonicalize`], through its closest existing ancestor if it doesn't exist yet.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+	let absolute = std::path::absolute(path)?;
+	for ancestor in absolute.ancestors() {
+		match ancestor.canonicalize() {
+			Ok(canonical) => {
+				return Ok(canonical.join(absolute.strip_prefix(ancestor).unwrap_or(&absolute)));
+			}
+			Err(error) if error.kind() == ErrorKind::NotFound => {}
+			Err(error) => return Err(error),
+		}
+	}
+	Ok(absolute)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The timestamp that determines how recently a file was used.
+pub enum TimeKey {
+	/// The later of the access and modification times.
+	Atime,
+	/// The modification time.
+	Mtime,
+	/// The status change time.
+	Ctime,
+	/// The birth time, if the filesystem records it.
+	Btime,
+	/// The latest of all available timestamps.
+	Max,
+}
+
+impl FromStr for TimeKey {
+	type Err = String;
+
+	fn from_str(raw: &str) -> Result<Self, Self::Err> {
+		Ok(match raw {
+			"atime" => Self::Atime,
+			"mtime" => Self::Mtime,
+			"ctime" => Self::Ctime,
+			"btime" => Self::Btime,
+			"max" => Self::Max,
+			_ => {
+				return Err(format!(
+					"unknown timestamp {raw:?}, expected one of atime, mtime, ctime, btime, max"
+				))
+			}
+		})
+	}
+}
+
+impl TimeKey {
+	/// Get the timestamp of the file.
+	///
+	/// Returns `None` if the timestamp is not available, which can only happen for birth times.
+	#[must_use]
+	pub fn get(self, metadata: &Metadata) -> Option<SystemTime> {
+		let atime = || unix_time(metadata.atime(), metadata.atime_nsec());
+		let mtime = || modified(metadata);
+		let ctime = || unix_time(metadata.ctime(), metadata.ctime_nsec());
+		let btime = || metadata.created().ok();
+
+		Some(match self {
+			// A write is also a use, and this keeps `noatime` mounts from looking untouched since creation.
+			Self::Atime => atime().max(mtime()),
+			Self::Mtime => mtime(),
+			Self::Ctime => ctime(),
+			Self::Btime => btime()?,
+			Self::Max => {
+				let latest = atime().max(mtime()).max(ctime());
+				btime().map_or(latest, |btime| btime.max(latest))
+			}
+		})
+	}
+}
+
+impl TimeKey {
+	/// Like [`TimeKey::get`], but falls back to the modification time.
+	///
+	/// Also returns whether the fallback was used.
+	pub(crate) fn get_or_modified(self, metadata: &Metadata) -> (SystemTime, bool) {
+		match self.get(metadata) {
+			Some(time) => (time, false),
+			None => (modified(metadata), true),
+		}
+	}
+}
+
+/// Like [`Metadata::modified`], but infallible on Unix.
+pub(crate) fn modified(metadata: &Metadata) -> SystemTime {
+	unix_time(metadata.mtime(), metadata.mtime_nsec())
+}
+
+fn unix_time(secs: i64, nsecs: i64) -> SystemTime {
+	// The nanoseconds are always non-negative, even for times before the epoch.
+	let nsecs = Duration::from_nanos(nsecs.try_into().unwrap_or(0));
+	let whole = Duration::from_secs(secs.unsigned_abs());
+	if secs < 0 {
+		UNIX_EPOCH - whole + nsecs
+	} else {
+		UNIX_EPOCH + whole + nsecs
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How the size of a file is measured.
+pub enum Usage {
+	/// The length of the file.
+	Apparent,
+	/// The space allocated for the file on disk.
+	Blocks,
+}
+
+impl FromStr for Usage {
+	type Err = String;
+
+	fn from_str(raw: &str) -> Result<Self, Self::Err> {
+		Ok(match raw {
+			"apparent" => Self::Apparent,
+			"blocks" => Self::Blocks,
+			_ => {
+				return Err(format!(
+					"unknown usage {raw:?}, expected one of apparent, blocks"
+				))
+			}
+		})
+	}
+}
+
+impl Usage {
+	/// Get the size of the file, in bytes.
+	#[must_use]
+	pub fn get(self, metadata: &Metadata) -> u64 {
+		match self {
+			Self::Apparent => metadata.size(),
+			// `st_blocks` is always in units of 512 bytes, regardless of the filesystem's block size.
+			Self::Blocks => metadata.blocks() * 512,
+		}
+	}
+}
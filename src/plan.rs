@@ -0,0 +1,689 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{FileType, Metadata};
+use std::io::ErrorKind;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use rustix::fs::{FlockOperation, Mode, OFlags};
+use rustix::io::Errno;
+use walkdir::WalkDir;
+
+use crate::archive::Archive;
+use crate::children::Children;
+use crate::error::{Errors, IoResultExt as _};
+use crate::open::open_files;
+use crate::protect::Rules;
+use crate::trash::trash;
+use crate::{Disposal, Error, Goal, Options};
+
+/// A file chosen for deletion.
+#[derive(Debug, Clone)]
+pub struct Victim {
+	/// The path of the file.
+	pub path: PathBuf,
+	/// The timestamp the file was ordered by.
+	pub time: SystemTime,
+	/// Whether this is the last remaining hard link to the file in the directory,
+	/// so that deleting it frees the file.
+	pub last_link: bool,
+	/// The size of the file, measured according to [`Options::usage`].
+	pub size: u64,
+	/// The space freed by deleting the file.
+	///
+	/// This is zero if other hard links to the same file remain in the directory.
+	pub freed: u64,
+	/// The projected size of the directory once this file is deleted.
+	pub size_after: u64,
+	/// Which part of the goal the file was chosen to meet.
+	pub reason: Reason,
+	/// The device and inode numbers, used to detect files that were replaced since planning.
+	inode: (u64, u64),
+}
+
+/// Which part of the [`Goal`] a [`Victim`] was chosen to meet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+	/// The size was over the limit, or space needed to be reclaimed.
+	Size,
+	/// There were too many files.
+	Files,
+	/// The child directory containing the file was over [`Goal::per_child`].
+	Child,
+}
+
+/// Something that happened while executing a [`Plan`].
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Event<'a> {
+	/// The victim is about to be deleted.
+	Deleting(&'a Victim),
+	/// The victim had already disappeared, so it was counted as freed.
+	Vanished(&'a Victim),
+	/// The victim was modified or replaced since planning, so it was left alone.
+	Changed(&'a Victim),
+	/// Another process holds a lock on the victim, so it was left alone.
+	Locked(&'a Victim),
+	/// A parent directory was deleted because it became empty.
+	RemovedDirectory(&'a Path),
+	/// An error occurred and the affected file was skipped.
+	Skipped(&'a Error),
+}
+
+/// The files to delete to bring a directory, or a pool of directories, under its [`Goal`].
+#[derive(Debug)]
+pub struct Plan {
+	/// The directories being limited, which share the goal and the order of eviction.
+	///
+	/// This is a single directory unless the plan was computed with [`Plan::compute_pooled`].
+	pub directories: Vec<PathBuf>,
+	/// Whether the directories themselves are kept when they become empty, unlike their subdirectories.
+	///
+	/// This is set for plans from a [`Watcher`](crate::Watcher), which would stop watching a directory that was deleted.
+	pub keep_directories: bool,
+	/// The goal the plan was computed for.
+	pub goal: Goal,
+	/// The options the plan was computed with.
+	pub options: Options,
+	/// The size of the directory before anything is deleted.
+	pub initial_size: u64,
+	/// The size of the directory once all victims are deleted.
+	pub projected_size: u64,
+	/// The number of files in the directory before anything is deleted.
+	///
+	/// Hard links to the same file are counted once.
+	pub initial_files: u64,
+	/// The number of files in the directory once all victims are deleted.
+	pub projected_files: u64,
+	/// The files to delete, least-recently used first.
+	///
+	/// Those chosen to meet [`Goal::per_child`] come before the others.
+	pub victims: Vec<Victim>,
+	/// Whether birth times were requested but unavailable for some files,
+	/// in which case their modification times were used instead.
+	pub birth_time_fallback: bool,
+	/// The number of files that would have been deleted to meet the goal,
+	/// but were left alone because they are younger than [`Options::min_age`].
+	pub too_young: u64,
+	/// The number of files that would have been deleted to meet the goal,
+	/// but were left alone because a process has them open.
+	pub in_use: u64,
+	/// The number of files that would have been deleted to meet the goal,
+	/// but were left alone because of [`Options::filter`] or the `.sauignore` and `.saukeep` rules.
+	pub protected: u64,
+	/// The number of child directories that are still over [`Goal::per_child`] once all victims are deleted.
+	pub children_over: u64,
+	/// The timestamp of the oldest file that is not a victim, if any.
+	pub oldest_kept: Option<SystemTime>,
+	/// The errors that were skipped while walking the directory.
+	///
+	/// The affected files are not counted towards the size and are never victims.
+	pub errors: Vec<Error>,
+}
+
+/// The result of executing a [`Plan`].
+#[derive(Debug)]
+pub struct Outcome {
+	/// The size of the directory after deleting the victims.
+	pub size: u64,
+	/// The number of files in the directory after deleting the victims.
+	pub files: u64,
+	/// The errors that were skipped while deleting the victims.
+	pub errors: Vec<Error>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Candidate {
+	pub path: PathBuf,
+	pub metadata: Metadata,
+	pub time: SystemTime,
+	/// Whether the filter allows deleting the file.
+	pub deletable: bool,
+}
+
+impl Candidate {
+	pub fn inode(&self) -> (u64, u64) {
+		(self.metadata.dev(), self.metadata.ino())
+	}
+}
+
+pub(crate) fn counted_file_type(ty: FileType) -> bool {
+	ty.is_file() || ty.is_symlink()
+}
+
+/// The files in the directory.
+#[derive(Debug, Default)]
+pub(crate) struct Scan {
+	pub files: Vec<Candidate>,
+	pub birth_time_fallback: bool,
+}
+
+impl Scan {
+	/// Add the file at `path` within `root`, the directory being limited.
+	///
+	/// `protected` is whether the `.sauignore` and `.saukeep` rules protect the file.
+	pub fn add(
+		&mut self,
+		root: &Path,
+		path: PathBuf,
+		metadata: Metadata,
+		options: &Options,
+		protected: bool,
+	) {
+		let relative = path.strip_prefix(root).unwrap_or(&path);
+		let allowed = options.filter.allows(relative);
+		// Protected files always count, only those left out by the filter may not.
+		if !allowed && !options.filter.count_excluded {
+			return;
+		}
+		let deletable = !protected && allowed;
+
+		let (time, fallback) = options.time.get_or_modified(&metadata);
+		self.birth_time_fallback |= fallback;
+		self.files.push(Candidate {
+			path,
+			metadata,
+			time,
+			deletable,
+		});
+	}
+
+	/// Add the files within `directory`, which is within `root`, calling `on_directory` for each subdirectory.
+	pub fn walk(
+		&mut self,
+		root: &Path,
+		directory: &Path,
+		options: &Options,
+		errors: &mut Errors,
+		mut on_directory: impl FnMut(&Path),
+	) -> Result<(), Error> {
+		let mut rules = Rules::new(root, directory, errors)?;
+		for entry in WalkDir::new(directory).min_depth(1) {
+			let Some(entry) = errors.check(entry.or_error(Error::Walk, directory))? else {
+				continue;
+			};
+			rules.leave_unrelated(entry.path());
+			if entry.file_type().is_dir() {
+				rules.enter(entry.path(), errors)?;
+				on_directory(entry.path());
+			}
+			if !counted_file_type(entry.file_type()) {
+				continue;
+			}
+
+			let Some(metadata) =
+				errors.check(entry.metadata().or_error(Error::Metadata, entry.path()))?
+			else {
+				continue;
+			};
+			let protected = rules.protects(entry.path());
+			self.add(root, entry.into_path(), metadata, options, protected);
+		}
+
+		Ok(())
+	}
+}
+
+impl Plan {
+	/// Walk `directory` and choose the least-recently used files to delete to meet `goal`.
+	///
+	/// A plain number of bytes can be passed as `goal` to limit the size without hysteresis.
+	///
+	/// # Errors
+	///
+	/// If walking the directory or getting the metadata of a file fails and the error policy is [`OnError::Abort`](crate::OnError::Abort),
+	/// or if [`Options::skip_open`] is set and the open files can't be listed.
+	pub fn compute(
+		directory: impl Into<PathBuf>,
+		goal: impl Into<Goal>,
+		options: Options,
+	) -> Result<Self, Error> {
+		Self::compute_pooled([directory], goal, options)
+	}
+
+	/// Walk all of `directories` and choose the least-recently used files to delete among them,
+	/// to meet `goal` for their combined size and number of files.
+	///
+	/// The directories must not overlap, or the files they share would be counted twice.
+	/// Filters and protection rules apply relative to the directory containing each file.
+	///
+	/// # Errors
+	///
+	/// Like [`Plan::compute`].
+	pub fn compute_pooled(
+		directories: impl IntoIterator<Item = impl Into<PathBuf>>,
+		goal: impl Into<Goal>,
+		options: Options,
+	) -> Result<Self, Error> {
+		let directories: Vec<PathBuf> = directories.into_iter().map(Into::into).collect();
+		let goal = goal.into();
+		let mut errors = Errors::new(options.on_error);
+		let mut scan = Scan::default();
+		for directory in &directories {
+			scan.walk(directory, directory, &options, &mut errors, |_| {})?;
+		}
+		Self::select(directories, goal, options, scan, errors.skipped)
+	}
+
+	pub(crate) fn select(
+		directories: Vec<PathBuf>,
+		goal: Goal,
+		options: Options,
+		scan: Scan,
+		errors: Vec<Error>,
+	) -> Result<Self, Error> {
+		let Scan {
+			mut files,
+			birth_time_fallback,
+		} = scan;
+		// `WalkDir::sort_by_key` only orders siblings, so everything is collected and sorted globally.
+		// Ties are broken by path so that runs are deterministic.
+		files.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.path.cmp(&b.path)));
+
+		// Deleting an open file frees nothing until it is closed, and breaks whatever is using it.
+		let open = if options.skip_open {
+			open_files()?
+		} else {
+			HashSet::new()
+		};
+		let mut selection = Selection::new(&directories, goal, &options, &files, open);
+		let initial_size = selection.size;
+		let initial_files = selection.remaining_files;
+		let target = goal.target(initial_size);
+
+		// Children over their own limit are brought under it first,
+		// so that the overall limit doesn't evict the files of other children in their place.
+		// The files that are left alone are remembered as counted in `too_young`, `in_use` or `protected`.
+		let mut rest = Vec::with_capacity(files.len());
+		for file in files {
+			if !selection.children.is_over(&file) {
+				rest.push((file, false));
+			} else if selection.skip(&file, true) {
+				rest.push((file, true));
+			} else {
+				selection.take(file, Reason::Child);
+			}
+		}
+
+		let mut oldest_kept = None;
+		for (file, counted) in rest {
+			if selection.size <= target && selection.remaining_files <= goal.max_files {
+				oldest_kept.get_or_insert(file.time);
+				break;
+			}
+
+			let reason = if selection.size > target {
+				Reason::Size
+			} else {
+				Reason::Files
+			};
+			if selection.skip(&file, !counted) {
+				oldest_kept.get_or_insert(file.time);
+				continue;
+			}
+			selection.take(file, reason);
+		}
+
+		let Selection {
+			size,
+			remaining_files,
+			children,
+			victims,
+			too_young,
+			in_use,
+			protected,
+			..
+		} = selection;
+		let children_over = children.over;
+		Ok(Self {
+			directories,
+			keep_directories: false,
+			goal,
+			options,
+			initial_size,
+			projected_size: size,
+			initial_files,
+			projected_files: remaining_files,
+			victims,
+			birth_time_fallback,
+			too_young,
+			in_use,
+			protected,
+			children_over,
+			oldest_kept,
+			errors,
+		})
+	}
+
+	/// Delete the victims, calling `on_event` as progress is made.
+	///
+	/// Other processes may be using the directory concurrently, so each victim is checked again right before it is deleted.
+	/// Victims that disappeared are counted as freed, and victims whose timestamp changed or that were replaced are left alone.
+	/// With [`Options::respect_locks`], victims that another process holds a `flock` on are left alone too.
+	///
+	/// Victims are disposed of according to [`Options::disposal`].
+	///
+	/// # Errors
+	///
+	/// If deleting a victim fails and the error policy is [`OnError::Abort`](crate::OnError::Abort).
+	pub fn execute(&self, mut on_event: impl FnMut(Event<'_>)) -> Result<Outcome, Error> {
+		let mut errors = Errors::new(self.options.on_error);
+		self.options.disposal.check(&self.directories)?;
+		let mut disposer = Disposer::new(self);
+		let mut size = self.initial_size;
+		let mut files = self.initial_files;
+		// Unlinking a hard link changes the status change time of the remaining links.
+		let mut unlinked = HashSet::new();
+
+		for victim in &self.victims {
+			let freed = match self.recheck(victim, unlinked.contains(&victim.inode)) {
+				Ok(Recheck::Unchanged { freed }) => freed,
+				Ok(Recheck::Vanished) => {
+					on_event(Event::Vanished(victim));
+					size = size.saturating_sub(victim.freed);
+					files -= u64::from(victim.last_link);
+					continue;
+				}
+				Ok(Recheck::Changed) => {
+					on_event(Event::Changed(victim));
+					continue;
+				}
+				Err(error) => {
+					on_event(Event::Skipped(errors.skip(error)?));
+					continue;
+				}
+			};
+
+			// The lock is held until the file is deleted.
+			let _lock = if self.options.respect_locks {
+				match lock(&victim.path) {
+					Ok(Lock::Acquired(lock)) => Some(lock),
+					Ok(Lock::Unlockable) => None,
+					Ok(Lock::Held) => {
+						on_event(Event::Locked(victim));
+						continue;
+					}
+					Err(error) => {
+						on_event(Event::Skipped(errors.skip(error)?));
+						continue;
+					}
+				}
+			} else {
+				None
+			};
+
+			on_event(Event::Deleting(victim));
+			match disposer.dispose(&victim.path) {
+				Ok(()) => {}
+				// It disappeared between the check and now.
+				// The error may be about something else, like the trash, so the victim itself is checked.
+				Err(error)
+					if error.io_error().kind() == ErrorKind::NotFound
+						&& std::fs::symlink_metadata(&victim.path)
+							.is_err_and(|error| error.kind() == ErrorKind::NotFound) => {}
+				Err(error) => {
+					on_event(Event::Skipped(errors.skip(error)?));
+					continue;
+				}
+			}
+			unlinked.insert(victim.inode);
+			size = size.saturating_sub(freed);
+			files -= u64::from(victim.last_link);
+
+			if !self.options.keep_parents {
+				let root = root(&self.directories, &victim.path);
+				let within = if self.keep_directories {
+					root
+				} else {
+					root.parent().unwrap_or(root)
+				};
+				remove_empty_ancestors(&victim.path, within, &mut on_event);
+			}
+		}
+
+		Ok(Outcome {
+			size,
+			files,
+			errors: errors.skipped,
+		})
+	}
+}
+
+/// The state of choosing the victims of a [`Plan`].
+struct Selection<'a> {
+	min_age: Duration,
+	now: SystemTime,
+	/// Hard links share an inode, so each inode is counted once
+	/// and only frees space once its last link in the tree is gone.
+	links_in_tree: HashMap<(u64, u64), Links>,
+	/// Deleting the other links to a file that must be kept would free nothing.
+	kept: HashSet<(u64, u64)>,
+	open: HashSet<(u64, u64)>,
+	/// The projected size, counting each inode once.
+	size: u64,
+	remaining_files: u64,
+	children: Children<'a>,
+	victims: Vec<Victim>,
+	too_young: u64,
+	in_use: u64,
+	protected: u64,
+}
+
+impl<'a> Selection<'a> {
+	fn new(
+		directories: &'a [PathBuf],
+		goal: Goal,
+		options: &Options,
+		files: &[Candidate],
+		open: HashSet<(u64, u64)>,
+	) -> Self {
+		let usage = options.usage;
+		let mut links_in_tree: HashMap<(u64, u64), Links> = HashMap::new();
+		let mut kept = HashSet::new();
+		let mut size: u64 = 0;
+		for file in files {
+			let links = links_in_tree.entry(file.inode()).or_default();
+			if links.count == 0 {
+				links.usage = usage.get(&file.metadata);
+				size += links.usage;
+			}
+			links.count += 1;
+			if !file.deletable {
+				kept.insert(file.inode());
+			}
+		}
+
+		Self {
+			min_age: options.min_age,
+			// Timestamps in the future count as brand new.
+			now: SystemTime::now(),
+			remaining_files: links_in_tree.len() as u64,
+			links_in_tree,
+			kept,
+			open,
+			size,
+			children: Children::new(directories, goal, files, usage),
+			victims: Vec::new(),
+			too_young: 0,
+			in_use: 0,
+			protected: 0,
+		}
+	}
+
+	/// Whether `file` must be left alone, counting it in `too_young`, `in_use` or `protected` if `count` is set.
+	fn skip(&mut self, file: &Candidate, count: bool) -> bool {
+		let inode = file.inode();
+		if self.kept.contains(&inode) {
+			self.protected += u64::from(count);
+			true
+		} else if self.now.duration_since(file.time).unwrap_or(Duration::ZERO) < self.min_age {
+			self.too_young += u64::from(count);
+			true
+		} else if self.open.contains(&inode) {
+			self.in_use += u64::from(count);
+			true
+		} else {
+			false
+		}
+	}
+
+	fn take(&mut self, file: Candidate, reason: Reason) {
+		let inode = file.inode();
+		let links = self.links_in_tree.entry(inode).or_default();
+		links.count -= 1;
+		let last_link = links.count == 0;
+		// The links may disagree about the size if the index is stale, so the size that was counted is the one freed.
+		let size = links.usage;
+		let freed = if last_link {
+			self.remaining_files -= 1;
+			size
+		} else {
+			0
+		};
+		self.size = self.size.saturating_sub(freed);
+		self.children.remove(&file);
+		self.victims.push(Victim {
+			path: file.path,
+			time: file.time,
+			last_link,
+			size,
+			freed,
+			size_after: self.size,
+			reason,
+			inode,
+		});
+	}
+}
+
+/// The links to an inode that remain, and the space it was counted as using.
+#[derive(Default)]
+pub(crate) struct Links {
+	pub count: u64,
+	pub usage: u64,
+}
+
+enum Recheck {
+	Unchanged { freed: u64 },
+	Vanished,
+	Changed,
+}
+
+impl Plan {
+	fn recheck(&self, victim: &Victim, unlinked_sibling: bool) -> Result<Recheck, Error> {
+		let metadata = match std::fs::symlink_metadata(&victim.path) {
+			Ok(metadata) => metadata,
+			Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Recheck::Vanished),
+			Err(error) => return Err(Error::Metadata(victim.path.clone(), error)),
+		};
+
+		let replaced = (metadata.dev(), metadata.ino()) != victim.inode;
+		let touched =
+			!unlinked_sibling && self.options.time.get_or_modified(&metadata).0 != victim.time;
+		if replaced || touched {
+			return Ok(Recheck::Changed);
+		}
+
+		// The file may have been resized without its timestamp changing, for example when ordering by birth time.
+		let freed = if victim.last_link {
+			self.options.usage.get(&metadata)
+		} else {
+			0
+		};
+		Ok(Recheck::Unchanged { freed })
+	}
+}
+
+/// Gets rid of victims according to the [`Disposal`].
+struct Disposer<'a> {
+	plan: &'a Plan,
+	/// When the plan started being executed, which names the quarantine batch.
+	started: SystemTime,
+	/// Opened once there is something to archive.
+	archive: Option<Archive>,
+}
+
+impl<'a> Disposer<'a> {
+	fn new(plan: &'a Plan) -> Self {
+		Self {
+			plan,
+			started: SystemTime::now(),
+			archive: None,
+		}
+	}
+
+	fn dispose(&mut self, path: &Path) -> Result<(), Error> {
+		let directories = &self.plan.directories;
+		let directory = root(directories, path);
+		match &self.plan.options.disposal {
+			Disposal::Delete => std::fs::remove_file(path).or_error(Error::Delete, path),
+			Disposal::Trash => trash(path, directory),
+			Disposal::Quarantine(quarantine) => quarantine.insert(path, directory, self.started),
+			Disposal::Archive(archive_path) => {
+				let archive = match &mut self.archive {
+					Some(archive) => archive,
+					None => self.archive.insert(Archive::open(archive_path)?),
+				};
+				// Names relative to each directory could clash within a pool, so they are made absolute like `tar` does.
+				let name = if directories.len() == 1 {
+					path.strip_prefix(directory).unwrap_or(path).to_owned()
+				} else {
+					let absolute = std::path::absolute(path).or_error(Error::Archive, path)?;
+					absolute.strip_prefix("/").unwrap_or(&absolute).to_owned()
+				};
+				archive.append(path, &name)?;
+				std::fs::remove_file(path).or_error(Error::Delete, path)
+			}
+		}
+	}
+}
+
+enum Lock {
+	Acquired(OwnedFd),
+	/// The file can't be locked, such as a symbolic link or a file that disappeared,
+	/// so deleting it can go ahead.
+	Unlockable,
+	/// Another process holds a lock on the file.
+	Held,
+}
+
+/// Try to take an exclusive `flock` on the file, without waiting for other processes to release theirs.
+fn lock(path: &Path) -> Result<Lock, Error> {
+	// Symbolic links are not followed, since it's the link that gets deleted and not its target.
+	let flags = OFlags::RDONLY | OFlags::NOFOLLOW | OFlags::NONBLOCK | OFlags::CLOEXEC;
+	let file = match rustix::fs::open(path, flags, Mode::empty()) {
+		Ok(file) => file,
+		Err(Errno::LOOP | Errno::NOENT) => return Ok(Lock::Unlockable),
+		Err(error) => return Err(Error::Lock(path.to_owned(), error.into())),
+	};
+	match rustix::fs::flock(&file, FlockOperation::NonBlockingLockExclusive) {
+		Ok(()) => Ok(Lock::Acquired(file)),
+		Err(Errno::WOULDBLOCK) => Ok(Lock::Held),
+		Err(error) => Err(Error::Lock(path.to_owned(), error.into())),
+	}
+}
+
+/// The directory among `directories` that contains `path`.
+pub(crate) fn root<'a>(directories: &'a [PathBuf], path: &Path) -> &'a Path {
+	directories
+		.iter()
+		.find(|directory| path.starts_with(directory))
+		.or(directories.first())
+		.map_or(Path::new(""), PathBuf::as_path)
+}
+
+/// Delete the directories containing `path` that have become empty, up to but not including `within`.
+fn remove_empty_ancestors(path: &Path, within: &Path, on_event: &mut impl FnMut(Event<'_>)) {
+	for ancestor in path.ancestors().skip(1) {
+		if ancestor == within || !ancestor.starts_with(within) {
+			break;
+		}
+
+		// This approach suits this subroutine because this is a secondary, non-critical part of the functionality.
+		// The error case includes "directory not empty", which is a termination condition regardless.
+		if std::fs::remove_dir(ancestor).is_err() {
+			break;
+		}
+		on_event(Event::RemovedDirectory(ancestor));
+	}
+}
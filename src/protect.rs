@@ -0,0 +1,143 @@
+use std::ffi::OsStr;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::error::Errors;
+use crate::Error;
+
+/// A file with gitignore-style patterns for files that must never be deleted,
+/// relative to the directory containing it.
+const IGNORE_FILE: &str = ".sauignore";
+/// A file that protects everything in the directory containing it.
+const KEEP_FILE: &str = ".saukeep";
+
+/// Whether `path` is a `.sauignore` or `.saukeep` file.
+pub(crate) fn is_rules_file(path: &Path) -> bool {
+	let name = path.file_name();
+	name == Some(OsStr::new(IGNORE_FILE)) || name == Some(OsStr::new(KEEP_FILE))
+}
+
+/// The protection rules that apply to the directory currently being walked.
+pub(crate) struct Rules {
+	/// The rules of the directory and its ancestors, outermost first.
+	levels: Vec<Level>,
+}
+
+struct Level {
+	directory: PathBuf,
+	ignore: Option<Gitignore>,
+	keep: bool,
+}
+
+impl Rules {
+	/// Load the rules for `directory` and its ancestors up to `root`.
+	pub fn new(root: &Path, directory: &Path, errors: &mut Errors) -> Result<Self, Error> {
+		let mut this = Self { levels: Vec::new() };
+		let mut ancestors: Vec<&Path> = directory
+			.ancestors()
+			.take_while(|ancestor| ancestor.starts_with(root))
+			.collect();
+		ancestors.reverse();
+		for ancestor in ancestors {
+			this.enter(ancestor, errors)?;
+		}
+		Ok(this)
+	}
+
+	/// Load the rules for `directory`, which must be within the directory that was last entered.
+	pub fn enter(&mut self, directory: &Path, errors: &mut Errors) -> Result<(), Error> {
+		self.leave_unrelated(directory);
+		let level = match Level::load(directory) {
+			Ok(level) => level,
+			Err(error) => {
+				errors.skip(error)?;
+				// Err on the side of keeping files whose rules are unknown.
+				Level {
+					directory: directory.to_owned(),
+					ignore: None,
+					keep: true,
+				}
+			}
+		};
+		self.levels.push(level);
+		Ok(())
+	}
+
+	/// Forget the rules of directories that don't contain `path`,
+	/// since the walk has moved on from them.
+	pub fn leave_unrelated(&mut self, path: &Path) {
+		while let Some(level) = self.levels.last() {
+			if path.starts_with(&level.directory) && path != level.directory {
+				break;
+			}
+			self.levels.pop();
+		}
+	}
+
+	/// Whether the file at `path`, within the directory that was last entered, must never be deleted.
+	pub fn protects(&self, path: &Path) -> bool {
+		if is_rules_file(path) || self.levels.iter().any(|level| level.keep) {
+			return true;
+		}
+
+		// Like Git, the innermost rule that matches wins.
+		for level in self.levels.iter().rev() {
+			let Some(ignore) = &level.ignore else {
+				continue;
+			};
+			let relative = path.strip_prefix(&level.directory).unwrap_or(path);
+			let matched = ignore.matched_path_or_any_parents(relative, false);
+			if matched.is_ignore() {
+				return true;
+			} else if matched.is_whitelist() {
+				return false;
+			}
+		}
+
+		false
+	}
+}
+
+impl Level {
+	fn load(directory: &Path) -> Result<Self, Error> {
+		let keep_path = directory.join(KEEP_FILE);
+		let keep = match std::fs::symlink_metadata(&keep_path) {
+			Ok(_) => true,
+			Err(error) if error.kind() == ErrorKind::NotFound => false,
+			Err(error) => return Err(Error::Rules(keep_path, error)),
+		};
+
+		let ignore_path = directory.join(IGNORE_FILE);
+		let ignore = match std::fs::read_to_string(&ignore_path) {
+			Ok(contents) => {
+				let mut builder = GitignoreBuilder::new(directory);
+				for line in contents.lines() {
+					builder
+						.add_line(Some(ignore_path.clone()), line)
+						.map_err(|error| invalid_rules(&ignore_path, error))?;
+				}
+				let ignore = builder
+					.build()
+					.map_err(|error| invalid_rules(&ignore_path, error))?;
+				Some(ignore)
+			}
+			Err(error) if error.kind() == ErrorKind::NotFound => None,
+			Err(error) => return Err(Error::Rules(ignore_path, error)),
+		};
+
+		Ok(Self {
+			directory: directory.to_owned(),
+			ignore,
+			keep,
+		})
+	}
+}
+
+fn invalid_rules(path: &Path, error: ignore::Error) -> Error {
+	Error::Rules(
+		path.to_owned(),
+		io::Error::new(ErrorKind::InvalidData, error),
+	)
+}
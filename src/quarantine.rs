@@ -0,0 +1,159 @@
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use rustix::fs::{RenameFlags, CWD};
+use walkdir::WalkDir;
+
+use crate::error::{Errors, IoResultExt as _};
+use crate::{Error, OnError};
+
+/// A directory that victims are moved into instead of being deleted, until a grace period has elapsed.
+///
+/// Victims are moved into a batch directory named after the time they were quarantined,
+/// keeping their paths relative to the directory being limited.
+/// The quarantine must be on the same filesystem as that directory and not within it,
+/// and each directory being limited should have its own quarantine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quarantine {
+	/// The quarantine directory.
+	pub directory: PathBuf,
+}
+
+impl Quarantine {
+	/// Use `directory` as the quarantine.
+	#[must_use]
+	pub fn new(directory: impl Into<PathBuf>) -> Self {
+		Self {
+			directory: directory.into(),
+		}
+	}
+
+	/// Move the file at `path`, which is within `root`, into the batch for `time`.
+	pub(crate) fn insert(&self, path: &Path, root: &Path, time: SystemTime) -> Result<(), Error> {
+		let error = |error: io::Error| Error::Quarantine(path.to_owned(), error);
+
+		let relative = path.strip_prefix(root).unwrap_or(path);
+		let destination = self.directory.join(batch_name(time)).join(relative);
+		if let Some(parent) = destination.parent() {
+			std::fs::create_dir_all(parent).map_err(error)?;
+		}
+		rename_no_replace(path, &destination).map_err(error)
+	}
+
+	/// Delete the batches that were quarantined at least `grace` ago, calling `on_purged` for each of them.
+	///
+	/// Entries that aren't batches are left alone.
+	/// Returns the errors that were skipped.
+	///
+	/// # Errors
+	///
+	/// If reading the quarantine or deleting a batch fails and `on_error` is [`OnError::Abort`].
+	pub fn purge(
+		&self,
+		grace: Duration,
+		on_error: OnError,
+		mut on_purged: impl FnMut(&Path),
+	) -> Result<Vec<Error>, Error> {
+		let mut errors = Errors::new(on_error);
+		let now = SystemTime::now();
+		let Some(batches) = errors.check(self.batches())? else {
+			return Ok(errors.skipped);
+		};
+		for (time, batch) in batches {
+			if now.duration_since(time).unwrap_or(Duration::ZERO) < grace {
+				continue;
+			}
+			if errors
+				.check(std::fs::remove_dir_all(&batch).or_error(Error::Delete, &batch))?
+				.is_some()
+			{
+				on_purged(&batch);
+			}
+		}
+		Ok(errors.skipped)
+	}
+
+	/// Move everything in the quarantine back into `root`, calling `on_restored` with the restored path of each file.
+	///
+	/// Files that exist again in `root` are left in the quarantine and reported as errors.
+	/// If a file was quarantined more than once, the most recent version is restored.
+	/// Returns the errors that were skipped.
+	///
+	/// # Errors
+	///
+	/// If reading the quarantine or restoring a file fails and `on_error` is [`OnError::Abort`].
+	pub fn restore(
+		&self,
+		root: &Path,
+		on_error: OnError,
+		mut on_restored: impl FnMut(&Path),
+	) -> Result<Vec<Error>, Error> {
+		let mut errors = Errors::new(on_error);
+		let Some(mut batches) = errors.check(self.batches())? else {
+			return Ok(errors.skipped);
+		};
+		batches.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+		for (_, batch) in batches {
+			// Contents first so that directories are empty by the time they are removed.
+			for entry in WalkDir::new(&batch).contents_first(true) {
+				let Some(entry) = errors.check(entry.or_error(Error::Walk, &batch))? else {
+					continue;
+				};
+				if entry.file_type().is_dir() {
+					// Directories that still contain files that couldn't be restored are kept.
+					let _ = std::fs::remove_dir(entry.path());
+					continue;
+				}
+
+				let relative = entry.path().strip_prefix(&batch).unwrap_or(entry.path());
+				let destination = root.join(relative);
+				let restored = destination
+					.parent()
+					.map_or(Ok(()), std::fs::create_dir_all)
+					.and_then(|()| rename_no_replace(entry.path(), &destination));
+				if errors
+					.check(restored.or_error(Error::Restore, &destination))?
+					.is_some()
+				{
+					on_restored(&destination);
+				}
+			}
+		}
+		Ok(errors.skipped)
+	}
+
+	/// The batches in the quarantine with the times they were quarantined.
+	fn batches(&self) -> Result<Vec<(SystemTime, PathBuf)>, Error> {
+		let entries = match std::fs::read_dir(&self.directory) {
+			Ok(entries) => entries,
+			// Nothing was ever quarantined.
+			Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(error) => return Err(Error::Walk(self.directory.clone(), error)),
+		};
+
+		let mut batches = Vec::new();
+		for entry in entries {
+			let entry = entry.or_error(Error::Walk, &self.directory)?;
+			let name = entry.file_name();
+			if let Some(time) = name.to_str().and_then(parse_batch_name) {
+				batches.push((time, entry.path()));
+			}
+		}
+		Ok(batches)
+	}
+}
+
+fn batch_name(time: SystemTime) -> String {
+	humantime::format_rfc3339_seconds(time).to_string()
+}
+
+fn parse_batch_name(name: &str) -> Option<SystemTime> {
+	humantime::parse_rfc3339(name).ok()
+}
+
+/// Rename without overwriting whatever is at `to`, failing with [`ErrorKind::AlreadyExists`] instead.
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+	rustix::fs::renameat_with(CWD, from, CWD, to, RenameFlags::NOREPLACE).map_err(Into::into)
+}
@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+use std::path::Path;
+use std::str::FromStr;
+
+use sau::{Goal, Reason, Victim};
+use serde::Serialize;
+
+/// How to print records of what happens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	/// Only the messages on stderr.
+	Text,
+	/// A JSON array of all records on stdout, once done.
+	Json,
+	/// Each record as a line of JSON on stdout, as it happens.
+	Ndjson,
+}
+
+impl FromStr for Format {
+	type Err = String;
+
+	fn from_str(raw: &str) -> Result<Self, Self::Err> {
+		Ok(match raw {
+			"text" => Self::Text,
+			"json" => Self::Json,
+			"ndjson" => Self::Ndjson,
+			_ => {
+				return Err(format!(
+					"unknown format {raw:?}, expected one of text, json, ndjson"
+				))
+			}
+		})
+	}
+}
+
+/// Something that happened.
+///
+/// This is the schema documented in the README, so changes must be backwards compatible.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record<'a> {
+	/// The directories were scanned.
+	Plan {
+		/// The first of `directories`, from when there could only be one.
+		directory: Cow<'a, str>,
+		directories: Vec<Cow<'a, str>>,
+		dry_run: bool,
+		initial_size: u64,
+		initial_files: u64,
+		goal: GoalRecord,
+	},
+	/// A file is being (or would be) disposed of.
+	Victim {
+		path: Cow<'a, str>,
+		action: &'static str,
+		reason: &'static str,
+		time: String,
+		last_link: bool,
+		size: u64,
+		freed: u64,
+		size_after: u64,
+	},
+	/// A victim disappeared on its own and was counted as freed.
+	Vanished { path: Cow<'a, str> },
+	/// A victim was modified or replaced since planning and was left alone.
+	Changed { path: Cow<'a, str> },
+	/// A victim was locked by another process and was left alone.
+	Locked { path: Cow<'a, str> },
+	/// A directory was deleted because it became empty.
+	RemovedDirectory { path: Cow<'a, str> },
+	/// A quarantined batch was deleted for good.
+	PurgedBatch { path: Cow<'a, str> },
+	/// An error occurred, which either affected a single file that was skipped, or aborted the run.
+	Error {
+		path: Cow<'a, str>,
+		message: String,
+		aborted: bool,
+	},
+	/// The outcome of a run.
+	Result {
+		directory: Cow<'a, str>,
+		size: u64,
+		files: u64,
+		goal_met: bool,
+		too_young: u64,
+		in_use: u64,
+		protected: u64,
+		children_over: u64,
+	},
+}
+
+/// The parts of the [`Goal`] that apply, with `null` for those that don't.
+#[derive(Serialize)]
+pub struct GoalRecord {
+	high: Option<u64>,
+	low: Option<u64>,
+	reclaim: u64,
+	max_files: Option<u64>,
+	per_child: Option<u64>,
+	child_depth: Option<usize>,
+}
+
+impl From<Goal> for GoalRecord {
+	fn from(goal: Goal) -> Self {
+		let limit = |value: u64| (value != u64::MAX).then_some(value);
+		Self {
+			high: limit(goal.high),
+			low: limit(goal.low),
+			reclaim: goal.reclaim,
+			max_files: limit(goal.max_files),
+			per_child: limit(goal.per_child),
+			child_depth: (goal.per_child != u64::MAX).then_some(goal.child_depth),
+		}
+	}
+}
+
+impl<'a> Record<'a> {
+	pub fn victim(victim: &'a Victim, action: &'static str) -> Self {
+		Self::Victim {
+			path: path(&victim.path),
+			action,
+			reason: match victim.reason {
+				Reason::Size => "size",
+				Reason::Files => "files",
+				Reason::Child => "child",
+			},
+			time: humantime::format_rfc3339_nanos(victim.time).to_string(),
+			last_link: victim.last_link,
+			size: victim.size,
+			freed: victim.freed,
+			size_after: victim.size_after,
+		}
+	}
+
+	pub fn error(error: &'a sau::Error, aborted: bool) -> Self {
+		Self::Error {
+			path: path(error.path()),
+			message: error.to_string(),
+			aborted,
+		}
+	}
+}
+
+/// Paths that aren't valid UTF-8 have the invalid parts replaced with U+FFFD.
+pub fn path(path: &Path) -> Cow<'_, str> {
+	path.to_string_lossy()
+}
+
+/// Prints records in the chosen [`Format`].
+pub struct Reporter {
+	format: Format,
+	/// For [`Format::Json`], the records so far.
+	records: Vec<String>,
+}
+
+impl Reporter {
+	pub fn new(format: Format) -> Self {
+		Self {
+			format,
+			records: Vec::new(),
+		}
+	}
+
+	pub fn record(&mut self, record: &Record<'_>) {
+		match self.format {
+			Format::Text => {}
+			Format::Json => self
+				.records
+				.push(serde_json::to_string(record).expect("records are always serializable")),
+			Format::Ndjson => println!(
+				"{}",
+				serde_json::to_string(record).expect("records are always serializable")
+			),
+		}
+	}
+
+	pub fn finish(self) {
+		// The records are already serialized, and joining them keeps their fields in order.
+		if self.format == Format::Json {
+			println!("[{}]", self.records.join(",\n"));
+		}
+	}
+}
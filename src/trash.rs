@@ -0,0 +1,187 @@
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{self, ErrorKind, Write as _};
+use std::os::unix::ffi::OsStrExt as _;
+use std::os::unix::fs::{DirBuilderExt as _, MetadataExt as _, OpenOptionsExt as _};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+
+use crate::Error;
+
+/// Move the file at `path`, which is within `directory`, to the trash on the same filesystem,
+/// following the [freedesktop.org Trash specification](https://specifications.freedesktop.org/trash-spec/latest/).
+///
+/// The trash must not be within `directory`, since the file would then never leave it.
+pub(crate) fn trash(path: &Path, directory: &Path) -> Result<(), Error> {
+	let error = |error: io::Error| Error::Trash(path.to_owned(), error);
+
+	// The path is recorded in the trash so that the file can be restored, so it must be absolute.
+	let name = path
+		.file_name()
+		.ok_or_else(|| error(ErrorKind::InvalidInput.into()))?;
+	let parent = path
+		.parent()
+		.unwrap_or(Path::new("."))
+		.canonicalize()
+		.map_err(error)?;
+	let absolute = parent.join(name);
+	let device = std::fs::symlink_metadata(&absolute).map_err(error)?.dev();
+
+	let (trash, original) = find_trash(&absolute, device).map_err(error)?;
+	if trash.starts_with(directory.canonicalize().map_err(error)?) {
+		return Err(error(io::Error::new(
+			ErrorKind::InvalidInput,
+			format!("the trash {trash:?} is within the directory being limited"),
+		)));
+	}
+
+	let files = trash.join("files");
+	let info = trash.join("info");
+	for directory in [&files, &info] {
+		DirBuilder::new()
+			.recursive(true)
+			.mode(0o700)
+			.create(directory)
+			.map_err(error)?;
+	}
+
+	let (trashed_name, mut info_file) = reserve_name(&info, name).map_err(error)?;
+	let info_path = info.join(info_name(&trashed_name));
+	let contents = format!(
+		"[Trash Info]\nPath={}\nDeletionDate={}\n",
+		encode_path(&original),
+		deletion_date(SystemTime::now()),
+	);
+	let moved = info_file
+		.write_all(contents.as_bytes())
+		.and_then(|()| std::fs::rename(&absolute, files.join(&trashed_name)));
+	if let Err(moving_error) = moved {
+		// Without the file, the info file would only confuse trash browsers.
+		let _ = std::fs::remove_file(info_path);
+		return Err(error(moving_error));
+	}
+	Ok(())
+}
+
+/// Find the trash on the filesystem with the given device number, creating it if needed.
+///
+/// Also returns the path to record for the file, which is relative to the top of the filesystem for its own trash.
+fn find_trash(absolute: &Path, device: u64) -> io::Result<(PathBuf, PathBuf)> {
+	let home_trash = data_home()?.join("Trash");
+	if nearest_existing_device(&home_trash)? == device {
+		return Ok((home_trash, absolute.to_owned()));
+	}
+
+	let top = mount_top(absolute, device)?;
+	let original = absolute.strip_prefix(&top).unwrap_or(absolute).to_owned();
+	let uid = rustix::process::getuid().as_raw();
+
+	// An administrator-provided `.Trash` must be a real directory with the sticky bit set,
+	// otherwise other users could tamper with it.
+	let shared = top.join(".Trash");
+	let shared_is_safe = std::fs::symlink_metadata(&shared)
+		.is_ok_and(|metadata| metadata.is_dir() && metadata.mode() & 0o1000 != 0);
+	let trash = if shared_is_safe {
+		shared.join(uid.to_string())
+	} else {
+		top.join(format!(".Trash-{uid}"))
+	};
+	Ok((trash, original))
+}
+
+fn data_home() -> io::Result<PathBuf> {
+	if let Some(data_home) =
+		std::env::var_os("XDG_DATA_HOME").filter(|path| Path::new(path).is_absolute())
+	{
+		return Ok(data_home.into());
+	}
+	let home = std::env::var_os("HOME").ok_or_else(|| {
+		io::Error::new(
+			ErrorKind::NotFound,
+			"neither `XDG_DATA_HOME` nor `HOME` is set",
+		)
+	})?;
+	Ok(Path::new(&home).join(".local/share"))
+}
+
+/// The device number of `path`, or of its nearest ancestor if it doesn't exist yet.
+fn nearest_existing_device(path: &Path) -> io::Result<u64> {
+	for ancestor in path.ancestors() {
+		match std::fs::metadata(ancestor) {
+			Ok(metadata) => return Ok(metadata.dev()),
+			Err(error) if error.kind() == ErrorKind::NotFound => {}
+			Err(error) => return Err(error),
+		}
+	}
+	Err(ErrorKind::NotFound.into())
+}
+
+/// The top directory of the filesystem containing `absolute`, whose device number is `device`.
+fn mount_top(absolute: &Path, device: u64) -> io::Result<PathBuf> {
+	let mut top = absolute.parent().unwrap_or(absolute);
+	while let Some(parent) = top.parent() {
+		if std::fs::metadata(parent)?.dev() != device {
+			break;
+		}
+		top = parent;
+	}
+	Ok(top.to_owned())
+}
+
+/// Choose a name in the trash that isn't used yet, by creating its info file.
+///
+/// Creating the info file exclusively is what claims the name, so that concurrent trashers don't collide.
+fn reserve_name(info: &Path, name: &OsStr) -> io::Result<(PathBuf, File)> {
+	for attempt in 1_u64.. {
+		let mut candidate = name.to_owned();
+		if attempt > 1 {
+			candidate.push(format!(".{attempt}"));
+		}
+		let candidate = PathBuf::from(candidate);
+		match OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(0o600)
+			.open(info.join(info_name(&candidate)))
+		{
+			Ok(file) => return Ok((candidate, file)),
+			Err(error) if error.kind() == ErrorKind::AlreadyExists => {}
+			Err(error) => return Err(error),
+		}
+	}
+	unreachable!("there are fewer names in the trash than `u64::MAX`")
+}
+
+fn info_name(name: &Path) -> PathBuf {
+	let mut info_name = name.as_os_str().to_owned();
+	info_name.push(".trashinfo");
+	info_name.into()
+}
+
+/// Percent-encode a path as the specification requires, keeping the separators.
+fn encode_path(path: &Path) -> String {
+	let mut encoded = String::new();
+	for &byte in path.as_os_str().as_bytes() {
+		if byte.is_ascii_alphanumeric() || b"/-_.!~*'()".contains(&byte) {
+			encoded.push(char::from(byte));
+		} else {
+			let _ = write!(encoded, "%{byte:02X}");
+		}
+	}
+	encoded
+}
+
+/// Format the deletion date as the specification requires, in local time without a timezone designator.
+///
+/// The local timezone comes from `TZ` or `/etc/localtime`, and is UTC if neither is usable.
+fn deletion_date(time: SystemTime) -> String {
+	let time = Timestamp::try_from(time).unwrap_or(Timestamp::UNIX_EPOCH);
+	time
+		.to_zoned(TimeZone::system())
+		.strftime("%Y-%m-%dT%H:%M:%S")
+		.to_string()
+}
@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use inotify::{EventMask, Events, Inotify, WatchDescriptor, WatchMask};
+use rustix::event::{poll, PollFd, PollFlags, Timespec};
+
+use crate::error::{Errors, IoResultExt as _};
+use crate::plan::{counted_file_type, root, Candidate, Scan};
+use crate::protect::{is_rules_file, Rules};
+use crate::{Error, Goal, Options, Plan, TimeKey};
+
+/// Keeps an index of the files in a directory, or a pool of directories, up to date as they change, using inotify.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use sau::{Options, Watcher};
+///
+/// let mut watcher = Watcher::new("/var/cache/things", Options::default())?;
+/// watcher.plan(1 << 30)?.execute(|_event| {})?;
+/// watcher.wait(Duration::from_secs(1))?;
+/// watcher.plan(1 << 30)?.execute(|_event| {})?;
+/// # Ok::<(), sau::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Watcher {
+	directories: Vec<PathBuf>,
+	options: Options,
+	inotify: Inotify,
+	/// The directory that each watch is for.
+	watches: HashMap<WatchDescriptor, PathBuf>,
+	/// Sorted so that the contents of a directory are contiguous.
+	files: BTreeMap<PathBuf, Candidate>,
+	birth_time_fallback: bool,
+	errors: Errors,
+}
+
+impl Watcher {
+	/// Walk `directory` and start watching it and all of its subdirectories.
+	///
+	/// # Errors
+	///
+	/// If inotify can't be initialized or the directory can't be watched,
+	/// or if walking the directory fails and the error policy is [`OnError::Abort`](crate::OnError::Abort).
+	pub fn new(directory: impl Into<PathBuf>, options: Options) -> Result<Self, Error> {
+		Self::new_pooled([directory], options)
+	}
+
+	/// Walk all of `directories` and start watching them, to plan for them as one pool like [`Plan::compute_pooled`].
+	///
+	/// # Errors
+	///
+	/// Like [`Watcher::new`].
+	pub fn new_pooled(
+		directories: impl IntoIterator<Item = impl Into<PathBuf>>,
+		options: Options,
+	) -> Result<Self, Error> {
+		let directories: Vec<PathBuf> = directories.into_iter().map(Into::into).collect();
+		let inotify = Inotify::init().or_error(Error::Watch, root(&directories, Path::new("")))?;
+
+		let mut this = Self {
+			directories,
+			errors: Errors::new(options.on_error),
+			options,
+			inotify,
+			watches: HashMap::new(),
+			files: BTreeMap::new(),
+			birth_time_fallback: false,
+		};
+		this.add_trees()?;
+		Ok(this)
+	}
+
+	/// Choose the least-recently used files to delete to meet `goal`.
+	///
+	/// The plan's errors are those that were skipped since the last plan.
+	///
+	/// # Errors
+	///
+	/// If [`Options::skip_open`] is set and the open files can't be listed.
+	pub fn plan(&mut self, goal: impl Into<Goal>) -> Result<Plan, Error> {
+		let scan = Scan {
+			files: self.files.values().cloned().collect(),
+			birth_time_fallback: self.birth_time_fallback,
+		};
+		let errors = std::mem::take(&mut self.errors.skipped);
+		let mut plan = Plan::select(
+			self.directories.clone(),
+			goal.into(),
+			self.options.clone(),
+			scan,
+			errors,
+		)?;
+		plan.keep_directories = true;
+		Ok(plan)
+	}
+
+	/// Block until something in the directories changes, then update the index.
+	///
+	/// Once the first change arrives, this waits for `debounce` so that bursts of writes are handled together.
+	///
+	/// # Errors
+	///
+	/// If reading inotify events fails,
+	/// or if updating the index fails and the error policy is [`OnError::Abort`](crate::OnError::Abort).
+	pub fn wait(&mut self, debounce: Duration) -> Result<(), Error> {
+		self.wait_timeout(debounce, Duration::MAX).map(|_| ())
+	}
+
+	/// Like [`Watcher::wait`], but give up once `timeout` has elapsed without any change.
+	///
+	/// Returns whether anything changed.
+	/// Files keep aging and the filesystem keeps filling up without any change in the directories,
+	/// so this lets the goal be checked again periodically.
+	///
+	/// # Errors
+	///
+	/// Like [`Watcher::wait`].
+	pub fn wait_timeout(&mut self, debounce: Duration, timeout: Duration) -> Result<bool, Error> {
+		// Timeouts too long to represent are as good as none.
+		let timeout = Timespec::try_from(timeout).ok();
+		let mut ready = [PollFd::new(&self.inotify, PollFlags::IN)];
+		let count = poll(&mut ready, timeout.as_ref())
+			.map_err(std::io::Error::from)
+			.or_error(Error::Watch, root(&self.directories, Path::new("")))?;
+		if count == 0 {
+			return Ok(false);
+		}
+
+		let mut buffer = [0; 4096];
+		let mut changes = Changes::default();
+		std::thread::sleep(debounce);
+		loop {
+			match self.inotify.read_events(&mut buffer) {
+				Ok(events) => changes.collect(events, &mut self.watches),
+				Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+				Err(error) => {
+					let directory = root(&self.directories, Path::new(""));
+					return Err(Error::Watch(directory.to_owned(), error));
+				}
+			}
+		}
+
+		if changes.overflowed {
+			// Some events were lost, so start over.
+			self.files.clear();
+			self.add_trees()?;
+			return Ok(true);
+		}
+
+		for path in changes.paths {
+			self.refresh(path)?;
+		}
+
+		Ok(true)
+	}
+
+	fn refresh(&mut self, path: PathBuf) -> Result<(), Error> {
+		if is_rules_file(&path) {
+			if let Some(parent) = path.parent() {
+				// The rules changed, so everything they apply to needs to be checked again.
+				let parent = parent.to_owned();
+				self.forget(&parent);
+				if !parent.exists() {
+					// The directory went away along with its rules, and gets its own events.
+					return Ok(());
+				}
+				return self.add_tree(&parent);
+			}
+		}
+
+		let metadata = match std::fs::symlink_metadata(&path) {
+			Ok(metadata) => metadata,
+			Err(error) if error.kind() == ErrorKind::NotFound => {
+				self.forget(&path);
+				return Ok(());
+			}
+			Err(error) => {
+				self.errors.skip(Error::Metadata(path, error))?;
+				return Ok(());
+			}
+		};
+
+		if metadata.is_dir() {
+			// It may have been moved in with contents, which don't get their own events.
+			self.add_tree(&path)
+		} else if counted_file_type(metadata.file_type()) {
+			if metadata.nlink() > 1 {
+				self.refresh_links(&path, &metadata);
+			}
+			let root = root(&self.directories, &path);
+			let parent = path.parent().unwrap_or(root);
+			let rules = Rules::new(root, parent, &mut self.errors)?;
+			let protected = rules.protects(&path);
+			let mut scan = Scan::default();
+			scan.add(root, path, metadata, &self.options, protected);
+			self.merge(scan);
+			Ok(())
+		} else {
+			self.forget(&path);
+			Ok(())
+		}
+	}
+
+	/// Update the other links to the same inode as `path`, which only get an event for the name that was used.
+	fn refresh_links(&mut self, path: &Path, metadata: &Metadata) {
+		let inode = (metadata.dev(), metadata.ino());
+		for (other, file) in &mut self.files {
+			if other != path && file.inode() == inode {
+				let (time, fallback) = self.options.time.get_or_modified(metadata);
+				self.birth_time_fallback |= fallback;
+				file.metadata = metadata.clone();
+				file.time = time;
+			}
+		}
+	}
+
+	/// Watch all of the directories and add their files to the index.
+	fn add_trees(&mut self) -> Result<(), Error> {
+		for directory in self.directories.clone() {
+			self.add_tree(&directory)?;
+		}
+		Ok(())
+	}
+
+	/// Watch `directory` and its subdirectories and add their files to the index.
+	fn add_tree(&mut self, directory: &Path) -> Result<(), Error> {
+		let mask = watch_mask(self.options.time);
+		let mut watch = |directory: &Path| {
+			self
+				.inotify
+				.watches()
+				.add(directory, mask)
+				.map(|descriptor| {
+					self.watches.insert(descriptor, directory.to_owned());
+				})
+				.or_error(Error::Watch, directory)
+		};
+
+		// Watch before walking so that files created in between aren't missed.
+		if let Err(error) = watch(directory) {
+			if self.directories.iter().any(|root| root == directory) {
+				return Err(error);
+			}
+			if let Error::Watch(_, error) = &error {
+				if error.kind() == ErrorKind::NotFound {
+					// It was removed before it could be watched.
+					self.forget(directory);
+					return Ok(());
+				}
+			}
+			self.errors.skip(error)?;
+			return Ok(());
+		}
+		let mut failed = Vec::new();
+		let mut scan = Scan::default();
+		scan.walk(
+			root(&self.directories, directory),
+			directory,
+			&self.options,
+			&mut self.errors,
+			|directory| {
+				if let Err(error) = watch(directory) {
+					failed.push(error);
+				}
+			},
+		)?;
+
+		for error in failed {
+			self.errors.skip(error)?;
+		}
+		self.merge(scan);
+		Ok(())
+	}
+
+	fn merge(&mut self, scan: Scan) {
+		self.birth_time_fallback |= scan.birth_time_fallback;
+		for file in scan.files {
+			self.files.insert(file.path.clone(), file);
+		}
+	}
+
+	/// Remove `path` and anything within it from the index.
+	fn forget(&mut self, path: &Path) {
+		let within: Vec<PathBuf> = self
+			.files
+			.range(path.to_owned()..)
+			.map(|(file, _)| file)
+			.take_while(|file| file.starts_with(path))
+			.cloned()
+			.collect();
+		for file in within {
+			self.files.remove(&file);
+		}
+	}
+}
+
+fn watch_mask(time: TimeKey) -> WatchMask {
+	let mut mask = WatchMask::CREATE
+		| WatchMask::DELETE
+		| WatchMask::MOVED_FROM
+		| WatchMask::MOVED_TO
+		| WatchMask::MODIFY
+		| WatchMask::CLOSE_WRITE
+		| WatchMask::ATTRIB
+		| WatchMask::ONLYDIR
+		| WatchMask::DONT_FOLLOW
+		| WatchMask::EXCL_UNLINK;
+	if matches!(time, TimeKey::Atime | TimeKey::Max) {
+		// Reads change the ordering too.
+		mask |= WatchMask::ACCESS;
+	}
+	mask
+}
+
+#[derive(Default)]
+struct Changes {
+	/// Sorted so that parents are handled before their contents.
+	paths: BTreeSet<PathBuf>,
+	overflowed: bool,
+}
+
+impl Changes {
+	fn collect(&mut self, events: Events<'_>, watches: &mut HashMap<WatchDescriptor, PathBuf>) {
+		for event in events {
+			if event.mask.contains(EventMask::Q_OVERFLOW) {
+				self.overflowed = true;
+			} else if event.mask.contains(EventMask::IGNORED) {
+				// The directory was deleted or moved out of the filesystem.
+				watches.remove(&event.wd);
+			} else if let Some(directory) = watches.get(&event.wd) {
+				let path = match event.name {
+					Some(name) => directory.join(name),
+					None => directory.clone(),
+				};
+				self.paths.insert(path);
+			}
+		}
+	}
+}
@@ -0,0 +1,973 @@
+use std::fs::{File, FileTimes};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use rustix::fs::{mkdirat, openat, Mode, OFlags, CWD};
+
+fn create(root: &Path, relative: &str, len: u64, age: Duration) {
+	let path = root.join(relative);
+	std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+	let file = File::create(&path).unwrap();
+	file.set_len(len).unwrap();
+	file.set_modified(SystemTime::now() - age).unwrap();
+}
+
+fn sau(args: &[&str], directory: &Path) {
+	let status = Command::new(env!("CARGO_BIN_EXE_sau"))
+		.args(args)
+		.arg(directory)
+		.status()
+		.unwrap();
+	assert!(status.success());
+}
+
+/// Nest directories deeper than `PATH_MAX` within `root`, so that walking them fails even as root.
+fn create_unreachable(root: &Path) {
+	let name = "d".repeat(255);
+	let mut directory = openat(CWD, root, OFlags::DIRECTORY, Mode::empty()).unwrap();
+	for _ in 0..20 {
+		mkdirat(&directory, &name, Mode::from_raw_mode(0o755)).unwrap();
+		directory = openat(&directory, &name, OFlags::DIRECTORY, Mode::empty()).unwrap();
+	}
+}
+
+#[test]
+fn eviction_order_is_global_across_directories() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	// Directory order is the opposite of age order, which per-directory sorting would get wrong.
+	create(root, "a/fresh", 100, hour);
+	create(root, "m/deep/nested/middle", 100, 2 * hour);
+	create(root, "z/stale", 100, 3 * hour);
+	create(root, "z/older/stalest", 100, 4 * hour);
+
+	sau(&["--size", "200B"], root);
+
+	assert!(root.join("a/fresh").exists());
+	assert!(root.join("m/deep/nested/middle").exists());
+	assert!(!root.join("z/stale").exists());
+	assert!(!root.join("z/older").exists());
+}
+
+#[test]
+fn hard_links_are_counted_once() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "a/stale", 100, 2 * hour);
+	create(root, "b/fresh", 100, hour);
+	std::fs::create_dir(root.join("c")).unwrap();
+	std::fs::hard_link(root.join("a/stale"), root.join("c/link")).unwrap();
+
+	// Three names but only two inodes, so nothing needs to be deleted.
+	sau(&["--size", "200B"], root);
+	assert!(root.join("a/stale").exists());
+	assert!(root.join("c/link").exists());
+
+	// Space is only freed once both names of the stale inode are gone.
+	let plan = sau::Plan::compute(root, 100, sau::Options::default()).unwrap();
+	let sizes: Vec<_> = plan
+		.victims
+		.iter()
+		.map(|victim| (victim.size, victim.freed))
+		.collect();
+	assert_eq!(sizes, [(100, 0), (100, 100)]);
+	sau(&["--size", "100B"], root);
+	assert!(!root.join("a/stale").exists());
+	assert!(!root.join("c/link").exists());
+	assert!(root.join("b/fresh").exists());
+}
+
+#[test]
+fn concurrent_changes_are_tolerated() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "vanishes", 100, 4 * hour);
+	create(root, "rewritten", 100, 3 * hour);
+	create(root, "stale", 100, 2 * hour);
+	create(root, "fresh", 100, hour);
+
+	let plan = sau::Plan::compute(root, 100, sau::Options::default()).unwrap();
+	assert_eq!(plan.victims.len(), 3);
+
+	std::fs::remove_file(root.join("vanishes")).unwrap();
+	std::fs::write(root.join("rewritten"), b"new contents").unwrap();
+
+	let mut vanished = Vec::new();
+	let mut changed = Vec::new();
+	let outcome = plan
+		.execute(|event| match event {
+			sau::Event::Vanished(victim) => vanished.push(victim.path.clone()),
+			sau::Event::Changed(victim) => changed.push(victim.path.clone()),
+			_ => {}
+		})
+		.unwrap();
+
+	assert_eq!(vanished, [root.join("vanishes")]);
+	assert_eq!(changed, [root.join("rewritten")]);
+	assert!(outcome.errors.is_empty());
+	assert_eq!(outcome.size, 200);
+	assert!(root.join("rewritten").exists());
+	assert!(!root.join("stale").exists());
+	assert!(root.join("fresh").exists());
+}
+
+#[test]
+fn errors_are_skipped_or_abort_the_run() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	create(root, "old", 100, Duration::from_secs(60 * 60));
+	create_unreachable(root);
+
+	let run = |on_error: &str| {
+		Command::new(env!("CARGO_BIN_EXE_sau"))
+			.args(["--size", "0B", "--on-error", on_error])
+			.arg(root)
+			.output()
+			.unwrap()
+	};
+
+	let output = run("abort");
+	assert_eq!(output.status.code(), Some(1));
+	assert!(root.join("old").exists());
+
+	let output = run("skip");
+	assert_eq!(output.status.code(), Some(2));
+	let stderr = String::from_utf8(output.stderr).unwrap();
+	assert!(stderr.contains("errors occurred, the affected files were skipped:"));
+	assert!(!root.join("old").exists());
+}
+
+#[test]
+fn files_are_ordered_by_the_chosen_time() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+	let now = SystemTime::now();
+
+	create(root, "born first", 100, Duration::ZERO);
+	std::thread::sleep(Duration::from_millis(10));
+	create(root, "born second", 100, Duration::ZERO);
+	let set_times = |name: &str, accessed: SystemTime, modified: SystemTime| {
+		let times = FileTimes::new()
+			.set_accessed(accessed)
+			.set_modified(modified);
+		File::options()
+			.write(true)
+			.open(root.join(name))
+			.unwrap()
+			.set_times(times)
+			.unwrap();
+	};
+	set_times("born first", now - 2 * hour, now - 2 * hour);
+	// Reading it since makes it the most recently used by access time.
+	set_times("born second", now, now - 3 * hour);
+
+	let order = |time: sau::TimeKey| {
+		let options = sau::Options {
+			time,
+			..sau::Options::default()
+		};
+		let plan = sau::Plan::compute(root, 0, options).unwrap();
+		let names: Vec<_> = plan
+			.victims
+			.iter()
+			.map(|victim| victim.path.file_name().unwrap().to_owned())
+			.collect();
+		(names, plan.birth_time_fallback)
+	};
+
+	assert_eq!(
+		order(sau::TimeKey::Mtime),
+		(vec!["born second".into(), "born first".into()], false)
+	);
+	assert_eq!(
+		order(sau::TimeKey::Atime),
+		(vec!["born first".into(), "born second".into()], false)
+	);
+	// Birth times are ordered like the files were created, unless the filesystem doesn't record them.
+	let has_birth_time = std::fs::metadata(root.join("born first"))
+		.unwrap()
+		.created()
+		.is_ok();
+	let (names, fallback) = order(sau::TimeKey::Btime);
+	assert_eq!(fallback, !has_birth_time);
+	if has_birth_time {
+		assert_eq!(names, ["born first", "born second"]);
+	} else {
+		assert_eq!(names, ["born second", "born first"]);
+	}
+}
+
+#[test]
+fn sparse_files_only_count_their_blocks() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "sparse", 1 << 20, 2 * hour);
+	std::fs::write(root.join("dense"), [1; 4096]).unwrap();
+
+	sau(&["--usage", "blocks", "--size", "64KiB"], root);
+	assert!(root.join("sparse").exists());
+
+	sau(&["--usage", "apparent", "--size", "64KiB"], root);
+	assert!(!root.join("sparse").exists());
+	assert!(root.join("dense").exists());
+}
+
+#[test]
+fn goals_combine_the_size_with_the_space_to_reclaim() {
+	assert_eq!(sau::Goal::reclaim(150).target(400), 250);
+	assert_eq!(sau::Goal::reclaim(500).target(400), 0);
+	assert_eq!(sau::Goal::new(300).and_reclaim(50).target(400), 300);
+	assert_eq!(sau::Goal::new(300).and_reclaim(150).target(400), 250);
+	// Space is reclaimed even under the high watermark.
+	assert_eq!(sau::Goal::watermarks(500, 200).target(400), 400);
+	assert_eq!(
+		sau::Goal::watermarks(500, 200).and_reclaim(50).target(400),
+		350
+	);
+}
+
+#[test]
+fn amounts_are_bytes_or_percentages_of_the_filesystem() {
+	use sau::Amount;
+
+	assert_eq!("1KiB".parse(), Ok(Amount::Bytes(1024)));
+	assert_eq!("1 KB".parse(), Ok(Amount::Bytes(1000)));
+	assert_eq!("10%".parse(), Ok(Amount::Percent(10.0)));
+	assert_eq!("2.5 %".parse(), Ok(Amount::Percent(2.5)));
+	assert!("101%".parse::<Amount>().is_err());
+	assert!("-1%".parse::<Amount>().is_err());
+	assert!("many%".parse::<Amount>().is_err());
+
+	assert_eq!(Amount::Bytes(1024).resolve(1 << 30), 1024);
+	assert_eq!(Amount::Percent(10.0).resolve(2000), 200);
+	assert_eq!(Amount::Percent(100.0).resolve(2000), 2000);
+}
+
+#[test]
+fn percentages_are_of_the_filesystem_capacity() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+
+	create(root, "file", 100, Duration::from_secs(60 * 60));
+	sau(&["--size", "100%"], root);
+	assert!(root.join("file").exists());
+	sau(&["--size", "0%"], root);
+	assert!(!root.join("file").exists());
+}
+
+#[test]
+fn min_free_frees_space_on_the_filesystem() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	// The files need blocks on disk for deleting them to free anything.
+	for (name, age) in [("old", 2 * hour), ("new", hour)] {
+		std::fs::write(root.join(name), [1; 4096]).unwrap();
+		File::options()
+			.write(true)
+			.open(root.join(name))
+			.unwrap()
+			.set_modified(SystemTime::now() - age)
+			.unwrap();
+	}
+
+	sau(&["--min-free", "0B"], root);
+	assert!(root.join("old").exists());
+
+	// More than any filesystem has available, so everything goes.
+	sau(&["--min-free", "1000PB"], root);
+	assert!(!root.join("old").exists());
+	assert!(!root.join("new").exists());
+}
+
+#[test]
+fn max_files_limits_the_number_of_files_on_its_own() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "oldest", 100, 4 * hour);
+	create(root, "sub/older", 100, 3 * hour);
+	create(root, "old", 100, 2 * hour);
+	create(root, "new", 100, hour);
+	// Links to the same file count as one.
+	std::fs::hard_link(root.join("new"), root.join("new link")).unwrap();
+
+	sau(&["--max-files", "2"], root);
+	assert!(!root.join("oldest").exists());
+	assert!(!root.join("sub").exists());
+	assert!(root.join("old").exists());
+	assert!(root.join("new").exists());
+	assert!(root.join("new link").exists());
+}
+
+#[test]
+fn min_free_measures_sparse_files_by_their_blocks() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "sparse", 1 << 30, 2 * hour);
+	std::fs::write(root.join("dense"), vec![1; 256 << 10]).unwrap();
+	let min_free = sau::Filesystem::containing(root).unwrap().available + (32 << 10);
+	let min_free = format!("{min_free}B");
+
+	let status = Command::new(env!("CARGO_BIN_EXE_sau"))
+		.args(["--min-free", &min_free, "--usage", "apparent"])
+		.arg(root)
+		.status()
+		.unwrap();
+	assert!(!status.success());
+
+	// Deleting the sparse file frees next to nothing, so the dense one has to go too.
+	sau(&["--min-free", &min_free], root);
+	assert!(!root.join("sparse").exists());
+	assert!(!root.join("dense").exists());
+}
+
+#[test]
+fn watermarks_leave_room_between_them() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+	let args = ["--high", "350B", "--low", "150B"];
+
+	create(root, "oldest", 100, 4 * hour);
+	create(root, "older", 100, 3 * hour);
+	create(root, "old", 100, 2 * hour);
+	sau(&args, root);
+	assert!(root.join("oldest").exists());
+
+	create(root, "new", 100, hour);
+	sau(&args, root);
+	assert!(!root.join("oldest").exists());
+	assert!(!root.join("older").exists());
+	assert!(!root.join("old").exists());
+	assert!(root.join("new").exists());
+}
+
+#[test]
+fn protection_rules_are_honored() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "pinned/old.bin", 100, 6 * hour);
+	create(root, "pinned/nested/old.bin", 100, 6 * hour);
+	std::fs::write(root.join("pinned/.saukeep"), "").unwrap();
+	create(root, "cache/keep.tar", 100, 5 * hour);
+	create(root, "cache/deep/keep.tar", 100, 5 * hour);
+	create(root, "cache/deep/unpinned.tar", 100, 5 * hour);
+	std::fs::write(root.join("cache/.sauignore"), "*.tar\n").unwrap();
+	std::fs::write(root.join("cache/deep/.sauignore"), "!unpinned.tar\n").unwrap();
+	create(root, "cache/evictable.bin", 100, 4 * hour);
+	create(root, "fresh.bin", 100, hour);
+
+	sau(&["--size", "0B"], root);
+
+	assert!(root.join("pinned/old.bin").exists());
+	assert!(root.join("pinned/nested/old.bin").exists());
+	assert!(root.join("pinned/.saukeep").exists());
+	assert!(root.join("cache/keep.tar").exists());
+	assert!(root.join("cache/deep/keep.tar").exists());
+	assert!(root.join("cache/.sauignore").exists());
+	assert!(!root.join("cache/deep/unpinned.tar").exists());
+	assert!(!root.join("cache/evictable.bin").exists());
+	assert!(!root.join("fresh.bin").exists());
+}
+
+#[test]
+fn protected_files_are_reported_when_the_limit_is_out_of_reach() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+
+	create(root, "pinned/old.bin", 100, Duration::from_secs(60 * 60));
+	std::fs::write(root.join("pinned/.saukeep"), "").unwrap();
+	create(root, "excluded.lock", 100, Duration::from_secs(60 * 60));
+
+	let output = Command::new(env!("CARGO_BIN_EXE_sau"))
+		.args(["--size", "0B", "--exclude", "*.lock", "--format", "ndjson"])
+		.arg(root)
+		.output()
+		.unwrap();
+	assert!(output.status.success());
+	let stderr = String::from_utf8(output.stderr).unwrap();
+	assert!(!stderr.contains("no need to delete anything"));
+	assert!(stderr.contains("3 files were not deleted because they are excluded or protected"));
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	let result: serde_json::Value =
+		serde_json::from_str(stdout.lines().next_back().unwrap()).unwrap();
+	assert_eq!(result["type"], "result");
+	assert_eq!(result["goal_met"], false);
+	assert_eq!(result["protected"], 3);
+}
+
+#[test]
+fn protected_files_count_even_when_only_deletable_ones_do() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "pinned.bin", 100, 3 * hour);
+	std::fs::write(root.join(".sauignore"), "pinned.bin\n").unwrap();
+	create(root, "excluded.lock", 100, 3 * hour);
+	create(root, "old.bin", 100, 2 * hour);
+	create(root, "new.bin", 100, hour);
+
+	// The pinned file and its rules still count, unlike the excluded one, so one more file has to go.
+	sau(
+		&[
+			"--size",
+			"250B",
+			"--exclude",
+			"*.lock",
+			"--count-deletable-only",
+		],
+		root,
+	);
+	assert!(root.join("pinned.bin").exists());
+	assert!(root.join("excluded.lock").exists());
+	assert!(!root.join("old.bin").exists());
+	assert!(root.join("new.bin").exists());
+}
+
+#[test]
+fn young_files_are_not_deleted() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let minute = Duration::from_secs(60);
+
+	create(root, "old", 100, 60 * minute);
+	create(root, "young", 100, 5 * minute);
+
+	sau(&["--size", "0B", "--min-age", "10m"], root);
+
+	assert!(!root.join("old").exists());
+	assert!(root.join("young").exists());
+}
+
+#[test]
+fn open_files_are_skipped() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "open", 100, 2 * hour);
+	create(root, "closed", 100, hour);
+	let mut holder = Command::new("sleep")
+		.arg("60")
+		.stdin(File::open(root.join("open")).unwrap())
+		.spawn()
+		.unwrap();
+
+	sau(&["--size", "0B", "--skip-open"], root);
+	holder.kill().unwrap();
+	holder.wait().unwrap();
+
+	assert!(root.join("open").exists());
+	assert!(!root.join("closed").exists());
+}
+
+#[test]
+fn locked_files_are_skipped() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "locked", 100, 2 * hour);
+	create(root, "unlocked", 100, hour);
+	let lock = File::open(root.join("locked")).unwrap();
+	rustix::fs::flock(&lock, rustix::fs::FlockOperation::LockShared).unwrap();
+
+	sau(&["--size", "0B", "--respect-locks"], root);
+
+	assert!(root.join("locked").exists());
+	assert!(!root.join("unlocked").exists());
+}
+
+#[test]
+fn trashed_files_can_be_restored() {
+	let root = tempfile::tempdir().unwrap();
+	let data_home = tempfile::tempdir_in(root.path()).unwrap();
+	let directory = root.path().join("cache");
+	let hour = Duration::from_secs(60 * 60);
+
+	create(&directory, "sub/old file", 100, 2 * hour);
+	create(&directory, "new", 100, hour);
+
+	let status = Command::new(env!("CARGO_BIN_EXE_sau"))
+		.args(["--size", "100B", "--trash"])
+		.arg(&directory)
+		.env("XDG_DATA_HOME", data_home.path())
+		// 14 hours ahead of UTC, so that the deletion date can only match in local time.
+		.env("TZ", "XXX-14")
+		.status()
+		.unwrap();
+	assert!(status.success());
+
+	assert!(!directory.join("sub/old file").exists());
+	assert!(directory.join("new").exists());
+	let trash = data_home.path().join("Trash");
+	assert_eq!(
+		std::fs::metadata(trash.join("files/old file"))
+			.unwrap()
+			.len(),
+		100
+	);
+	let info = std::fs::read_to_string(trash.join("info/old file.trashinfo")).unwrap();
+	let original = directory.canonicalize().unwrap().join("sub/old%20file");
+	assert!(info.starts_with("[Trash Info]\n"));
+	assert!(info.contains(&format!("\nPath={}\n", original.display())));
+	let date = info.split("\nDeletionDate=").nth(1).unwrap().trim_end();
+	let date: jiff::civil::DateTime = date.parse().unwrap();
+	let expected = jiff::Timestamp::now()
+		.to_zoned(jiff::tz::TimeZone::fixed(jiff::tz::offset(14)))
+		.datetime();
+	assert!(expected.duration_since(date).as_secs().abs() < 60, "{date}");
+}
+
+#[test]
+fn quarantined_files_can_be_restored_until_purged() {
+	let root = tempfile::tempdir().unwrap();
+	let directory = root.path().join("cache");
+	let quarantine = root.path().join("quarantine");
+	let quarantine_arg = quarantine.to_str().unwrap();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(&directory, "sub/old", 100, 2 * hour);
+	create(&directory, "new", 100, hour);
+
+	sau(
+		&["--size", "100B", "--quarantine", quarantine_arg],
+		&directory,
+	);
+	assert!(!directory.join("sub/old").exists());
+	assert!(directory.join("new").exists());
+
+	sau(&["restore", "--quarantine", quarantine_arg], &directory);
+	assert_eq!(
+		std::fs::metadata(directory.join("sub/old")).unwrap().len(),
+		100
+	);
+
+	sau(
+		&["--size", "100B", "--quarantine", quarantine_arg],
+		&directory,
+	);
+	sau(
+		&[
+			"--size",
+			"100B",
+			"--quarantine",
+			quarantine_arg,
+			"--grace",
+			"0s",
+		],
+		&directory,
+	);
+	sau(&["restore", "--quarantine", quarantine_arg], &directory);
+	assert!(!directory.join("sub/old").exists());
+	assert_eq!(std::fs::read_dir(&quarantine).unwrap().count(), 0);
+
+	// Without a quarantine, files would be deleted right away regardless of the grace period.
+	let status = Command::new(env!("CARGO_BIN_EXE_sau"))
+		.args(["--size", "0B", "--grace", "1h"])
+		.arg(&directory)
+		.status()
+		.unwrap();
+	assert!(!status.success());
+	assert!(directory.join("new").exists());
+}
+
+#[test]
+fn archived_files_are_kept_in_the_archive() {
+	let root = tempfile::tempdir().unwrap();
+	let directory = root.path().join("logs");
+	let archive = root.path().join("logs.tar.zst");
+	let hour = Duration::from_secs(60 * 60);
+
+	create(&directory, "app/old.log", 100, 3 * hour);
+	create(&directory, "older.log", 100, 4 * hour);
+	create(&directory, "new.log", 100, hour);
+
+	sau(
+		&["--size", "150B", "--archive", archive.to_str().unwrap()],
+		&directory,
+	);
+	create(&directory, "newest.log", 100, Duration::ZERO);
+	sau(
+		&["--size", "150B", "--archive", archive.to_str().unwrap()],
+		&directory,
+	);
+
+	assert!(directory.join("newest.log").exists());
+	let decoder = zstd::Decoder::new(File::open(&archive).unwrap()).unwrap();
+	let mut reader = tar::Archive::new(decoder);
+	let entries: Vec<_> = reader
+		.entries()
+		.unwrap()
+		.map(|entry| {
+			let entry = entry.unwrap();
+			(entry.path().unwrap().into_owned(), entry.size())
+		})
+		.collect();
+	assert_eq!(
+		entries,
+		[
+			(PathBuf::from("older.log"), 100),
+			(PathBuf::from("app/old.log"), 100),
+			(PathBuf::from("new.log"), 100),
+		]
+	);
+}
+
+#[test]
+fn disposals_within_the_directory_are_rejected_before_anything_is_created() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	create(root, "old", 100, Duration::from_secs(60 * 60));
+
+	for (option, path) in [
+		("--quarantine", root.join("sub/quarantine")),
+		("--archive", root.join("sub/logs.tar.zst")),
+	] {
+		let output = Command::new(env!("CARGO_BIN_EXE_sau"))
+			.args(["--size", "0B", option])
+			.arg(&path)
+			.arg(root)
+			.output()
+			.unwrap();
+		assert!(!output.status.success());
+		let stderr = String::from_utf8(output.stderr).unwrap();
+		assert!(stderr.contains("is within the directory"), "{stderr}");
+		assert!(root.join("old").exists());
+		assert!(!root.join("sub").exists());
+	}
+}
+
+#[test]
+fn records_are_printed_as_ndjson() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "sub/old", 100, 2 * hour);
+	create(root, "new", 100, hour);
+
+	let output = Command::new(env!("CARGO_BIN_EXE_sau"))
+		.args(["--size", "100B", "--format", "ndjson"])
+		.arg(root)
+		.output()
+		.unwrap();
+	assert!(output.status.success());
+	let records: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+		.unwrap()
+		.lines()
+		.map(|line| serde_json::from_str(line).unwrap())
+		.collect();
+
+	let types: Vec<_> = records
+		.iter()
+		.map(|record| record["type"].as_str().unwrap())
+		.collect();
+	assert_eq!(types, ["plan", "victim", "removed_directory", "result"]);
+	assert_eq!(records[0]["initial_size"], 200);
+	assert_eq!(records[0]["goal"]["high"], 100);
+	assert_eq!(records[0]["goal"]["max_files"], serde_json::Value::Null);
+	assert_eq!(records[1]["path"], root.join("sub/old").to_str().unwrap());
+	assert_eq!(records[1]["action"], "delete");
+	assert_eq!(records[1]["reason"], "size");
+	assert_eq!(records[1]["size"], 100);
+	assert_eq!(records[1]["freed"], 100);
+	assert_eq!(records[3]["size"], 100);
+	assert_eq!(records[3]["goal_met"], true);
+}
+
+#[test]
+fn metrics_are_written_to_a_file() {
+	let root = tempfile::tempdir().unwrap();
+	let directory = root.path().join("cache");
+	let metrics = root.path().join("sau.prom");
+	let hour = Duration::from_secs(60 * 60);
+
+	create(&directory, "old", 100, 2 * hour);
+	create(&directory, "new", 100, hour);
+
+	sau(
+		&[
+			"--size",
+			"100B",
+			"--metrics-file",
+			metrics.to_str().unwrap(),
+		],
+		&directory,
+	);
+
+	let metrics = std::fs::read_to_string(metrics).unwrap();
+	let value = |name: &str| {
+		metrics
+			.lines()
+			.find(|line| line.starts_with(&format!("{name}{{")))
+			.and_then(|line| line.rsplit(' ').next())
+			.map(|value| value.parse::<f64>().unwrap())
+	};
+	assert_eq!(value("sau_size_before_bytes"), Some(200.0));
+	assert_eq!(value("sau_size_after_bytes"), Some(100.0));
+	assert_eq!(value("sau_deleted_files"), Some(1.0));
+	assert_eq!(value("sau_freed_bytes"), Some(100.0));
+	assert_eq!(value("sau_errors"), Some(0.0));
+	let age = value("sau_oldest_file_age_seconds").unwrap();
+	assert!((3500.0..3700.0).contains(&age));
+	assert!(metrics.contains("# TYPE sau_freed_bytes_total counter\n"));
+}
+
+#[test]
+fn configured_directories_have_their_own_settings() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "logs/old.log", 100, 3 * hour);
+	create(root, "logs/new.log", 100, hour);
+	create(root, "cache/a", 100, 3 * hour);
+	create(root, "cache/b", 100, 2 * hour);
+	create(root, "cache/c.lock", 100, 4 * hour);
+	std::fs::write(
+		root.join("sau.toml"),
+		r#"
+			[[directory]]
+			path = "logs"
+			size = "100B"
+
+			[[directory]]
+			path = "cache"
+			max-files = 1
+			exclude = ["*.lock"]
+			min-age = "150m"
+		"#,
+	)
+	.unwrap();
+
+	let status = Command::new(env!("CARGO_BIN_EXE_sau"))
+		.arg("--config")
+		.arg(root.join("sau.toml"))
+		.status()
+		.unwrap();
+	assert!(status.success());
+
+	assert!(!root.join("logs/old.log").exists());
+	assert!(root.join("logs/new.log").exists());
+	// The excluded file still counts, and the younger file is kept because of the minimum age.
+	assert!(!root.join("cache/a").exists());
+	assert!(root.join("cache/b").exists());
+	assert!(root.join("cache/c.lock").exists());
+}
+
+#[test]
+fn pooled_directories_share_the_limit() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let first = root.join("first");
+	let second = root.join("second");
+	let hour = Duration::from_secs(60 * 60);
+
+	create(&first, "old/stale", 100, 4 * hour);
+	create(&second, "fresh", 100, hour);
+	create(&second, "old/stale", 100, 3 * hour);
+	create(&first, "middle", 100, 2 * hour);
+	// Anchored patterns are relative to the directory each file is in.
+	create(&second, "keep/oldest", 100, 5 * hour);
+
+	sau(
+		&[
+			"--size",
+			"300B",
+			"--exclude",
+			"/keep",
+			first.to_str().unwrap(),
+		],
+		&second,
+	);
+
+	assert!(!first.join("old").exists());
+	assert!(!second.join("old").exists());
+	assert!(first.join("middle").exists());
+	assert!(second.join("fresh").exists());
+	assert!(second.join("keep/oldest").exists());
+}
+
+#[test]
+fn each_child_is_limited_on_its_own() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	// The quiet tenant's file is the oldest, so an overall limit alone would evict it first.
+	create(root, "quiet/old", 100, 5 * hour);
+	create(root, "greedy/a", 100, 3 * hour);
+	create(root, "greedy/b", 100, 2 * hour);
+	create(root, "greedy/deep/c", 100, hour);
+
+	sau(&["--per-child", "200B", "--size", "300B"], root);
+
+	assert!(root.join("quiet/old").exists());
+	assert!(!root.join("greedy/a").exists());
+	assert!(root.join("greedy/b").exists());
+	assert!(root.join("greedy/deep/c").exists());
+
+	// One level deeper, the files directly within `greedy` are limited apart from `greedy/deep`.
+	create(root, "greedy/deep/d", 100, hour / 2);
+	sau(&["--per-child", "100B", "--child-depth", "2"], root);
+
+	assert!(root.join("quiet/old").exists());
+	assert!(root.join("greedy/b").exists());
+	assert!(!root.join("greedy/deep/c").exists());
+	assert!(root.join("greedy/deep/d").exists());
+}
+
+#[test]
+fn hard_links_with_stale_sizes_do_not_underflow() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+
+	create(root, "first", 100, 2 * hour);
+	std::fs::hard_link(root.join("first"), root.join("second")).unwrap();
+	let mut watcher = sau::Watcher::new(root, sau::Options::default()).unwrap();
+
+	// Growing the file through one name may leave the other name with the old size in the index.
+	std::fs::OpenOptions::new()
+		.append(true)
+		.open(root.join("second"))
+		.unwrap()
+		.set_len(1000)
+		.unwrap();
+	create(root, "new", 100, Duration::ZERO);
+	watcher.wait(Duration::from_millis(100)).unwrap();
+
+	let plan = watcher.plan(100).unwrap();
+	assert!(plan.projected_size <= plan.initial_size);
+	assert!(plan
+		.victims
+		.iter()
+		.all(|victim| victim.path != root.join("new")));
+}
+
+#[test]
+fn watched_hard_links_are_refreshed_together() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+
+	create(root, "first", 100, Duration::from_secs(60 * 60));
+	std::fs::hard_link(root.join("first"), root.join("second")).unwrap();
+	let mut watcher = sau::Watcher::new(root, sau::Options::default()).unwrap();
+
+	std::fs::OpenOptions::new()
+		.append(true)
+		.open(root.join("second"))
+		.unwrap()
+		.set_len(1000)
+		.unwrap();
+	watcher.wait(Duration::from_millis(100)).unwrap();
+
+	let plan = watcher.plan(0).unwrap();
+	assert_eq!(plan.initial_size, 1000);
+	// Both names now have the new modification time, so they are still evicted together.
+	assert_eq!(plan.victims.len(), 2);
+	assert_eq!(plan.victims[0].time, plan.victims[1].time);
+}
+
+#[test]
+fn watched_directories_with_rules_can_be_removed() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+
+	create(root, "kept", 100, Duration::from_secs(60 * 60));
+	create(root, "sub/.sauignore", 0, Duration::ZERO);
+	create(root, "sub/deep/file", 100, Duration::from_secs(60 * 60));
+	let mut watcher = sau::Watcher::new(root, sau::Options::default()).unwrap();
+
+	std::fs::remove_dir_all(root.join("sub")).unwrap();
+	watcher.wait(Duration::from_millis(100)).unwrap();
+
+	let plan = watcher.plan(0).unwrap();
+	assert_eq!(plan.initial_size, 100);
+}
+
+#[test]
+fn waiting_can_time_out() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let mut watcher = sau::Watcher::new(root, sau::Options::default()).unwrap();
+
+	assert!(!watcher
+		.wait_timeout(Duration::ZERO, Duration::from_millis(10))
+		.unwrap());
+	create(root, "new", 100, Duration::ZERO);
+	assert!(watcher
+		.wait_timeout(Duration::ZERO, Duration::from_secs(10))
+		.unwrap());
+	assert_eq!(watcher.plan(0).unwrap().initial_size, 100);
+}
+
+#[test]
+fn emptied_directories_are_only_kept_when_watched() {
+	let parent = tempfile::tempdir().unwrap();
+	let root = parent.path().join("cache");
+	create(&root, "sub/old", 100, Duration::from_secs(60 * 60));
+
+	let mut watcher = sau::Watcher::new(&root, sau::Options::default()).unwrap();
+	watcher.plan(0).unwrap().execute(|_event| {}).unwrap();
+	assert!(!root.join("sub").exists());
+	assert!(root.exists());
+
+	create(&root, "sub/old", 100, Duration::from_secs(60 * 60));
+	sau(&["--size", "0B"], &root);
+	assert!(!root.exists());
+}
+
+#[test]
+fn watched_changes_update_the_index() {
+	let root = tempfile::tempdir().unwrap();
+	let root = root.path();
+	let hour = Duration::from_secs(60 * 60);
+	let outside = tempfile::tempdir().unwrap();
+
+	create(root, "deleted", 100, 3 * hour);
+	create(root, "renamed", 100, 2 * hour);
+	create(outside.path(), "moved in/file", 100, 4 * hour);
+	let mut watcher = sau::Watcher::new(root, sau::Options::default()).unwrap();
+
+	create(root, "sub/created", 100, hour);
+	std::fs::remove_file(root.join("deleted")).unwrap();
+	std::fs::rename(root.join("renamed"), root.join("sub/renamed")).unwrap();
+	std::fs::rename(outside.path().join("moved in"), root.join("moved in")).unwrap();
+	watcher.wait(Duration::from_millis(100)).unwrap();
+
+	let plan = watcher.plan(0).unwrap();
+	assert_eq!(plan.initial_files, 3);
+	let victims: Vec<_> = plan
+		.victims
+		.iter()
+		.map(|victim| victim.path.strip_prefix(root).unwrap())
+		.collect();
+	assert_eq!(
+		victims,
+		[
+			Path::new("moved in/file"),
+			Path::new("sub/renamed"),
+			Path::new("sub/created"),
+		]
+	);
+}